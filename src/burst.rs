@@ -0,0 +1,195 @@
+use std::time::Duration;
+
+use super::resource::Burst;
+use super::units::{BytesPerSecond, Iops};
+
+// credit based bursting lets a disk or vm run at its burst limits for
+// this long with a full bucket.
+pub const BURST_DURATION_SECS: u64 = 30 * 60;
+
+/// Returns the credit based burst limits for a disk tier, for tiers
+/// which support it. Larger tiers only support on-demand bursting,
+/// which is not credit based.
+pub fn disk_burst(tier: &str) -> Option<Burst> {
+    let (max_iops, max_bandwidth_mbps) = match tier {
+        "P1" | "P2" | "P3" | "P4" | "P6" | "P10" | "P15" | "P20" => (3500.0, 170.0),
+        "E1" | "E2" | "E3" | "E4" | "E6" | "E10" | "E15" | "E20" => (600.0, 150.0),
+        "E30" => (1000.0, 250.0),
+        _ => return None,
+    };
+
+    Some(Burst {
+        max_iops: Iops(max_iops),
+        max_bandwidth: BytesPerSecond::from_megabytes(max_bandwidth_mbps),
+        duration_secs: BURST_DURATION_SECS,
+    })
+}
+
+/// Returns the credit based uncached burst limits of a vm size, for
+/// sizes which support vm level bursting, as published in the B-series,
+/// Dsv4 and Esv4 size tables.
+pub fn vm_burst(name: &str) -> Option<Burst> {
+    let (max_iops, max_bandwidth_mbps) = match name {
+        "Standard_B1ls" | "Standard_B1s" | "Standard_B1ms" | "Standard_B2s" | "Standard_B2ms" => {
+            (4000.0, 100.0)
+        }
+        "Standard_B4ms" | "Standard_B8ms" => (8000.0, 200.0),
+        "Standard_B12ms" | "Standard_B16ms" | "Standard_B20ms" => (16000.0, 400.0),
+        "Standard_D2s_v4" | "Standard_E2s_v4" => (4000.0, 200.0),
+        "Standard_D4s_v4" | "Standard_E4s_v4" => (8000.0, 200.0),
+        "Standard_D8s_v4" | "Standard_E8s_v4" => (16000.0, 400.0),
+        "Standard_D16s_v4" | "Standard_E16s_v4" => (32000.0, 800.0),
+        "Standard_E20s_v4" => (40000.0, 1000.0),
+        "Standard_D32s_v4" | "Standard_E32s_v4" => (64000.0, 1600.0),
+        "Standard_D48s_v4" | "Standard_E48s_v4" | "Standard_D64s_v4" | "Standard_E64s_v4" => {
+            (80000.0, 2000.0)
+        }
+        _ => return None,
+    };
+
+    Some(Burst {
+        max_iops: Iops(max_iops),
+        max_bandwidth: BytesPerSecond::from_megabytes(max_bandwidth_mbps),
+        duration_secs: BURST_DURATION_SECS,
+    })
+}
+
+/// Bucket estimates the burst credits of a single limit. Credits accrue
+/// while usage is below the baseline and are spent while it is above,
+/// and a full bucket sustains the burst limit for the burst duration.
+/// The real bucket state isn't observable, so the estimate starts full.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bucket {
+    baseline: f64,
+    burst: f64,
+    capacity: f64,
+    credits: f64,
+}
+
+impl Bucket {
+    pub fn new(baseline: f64, burst: f64, duration: Duration) -> Self {
+        let capacity = (burst - baseline).max(0.0) * duration.as_secs_f64();
+        Bucket {
+            baseline,
+            burst,
+            capacity,
+            credits: capacity,
+        }
+    }
+
+    /// Accrues or spends credits for usage sustained over elapsed. Usage
+    /// above the burst limit is throttled, so it spends no more than
+    /// running at the burst limit.
+    pub fn update(&mut self, usage: f64, elapsed: Duration) {
+        let delta = (self.baseline - usage.min(self.burst)) * elapsed.as_secs_f64();
+        self.credits = (self.credits + delta).max(0.0).min(self.capacity);
+    }
+
+    pub fn credits(&self) -> f64 {
+        self.credits
+    }
+
+    /// Returns the seconds until the bucket is exhausted at the current
+    /// usage, or at the burst limit while usage is within the baseline.
+    pub fn seconds_remaining(&self, usage: f64) -> f64 {
+        let spend = if usage > self.baseline {
+            usage.min(self.burst) - self.baseline
+        } else {
+            self.burst - self.baseline
+        };
+
+        if spend > 0.0 {
+            self.credits / spend
+        } else {
+            0.0
+        }
+    }
+
+    /// Returns whether usage is above the baseline and still being
+    /// served from credits.
+    pub fn is_bursting(&self, usage: f64) -> bool {
+        usage > self.baseline && self.credits > 0.0
+    }
+}
+
+/// Credits holds the IOPS and bandwidth buckets of a device or host.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Credits {
+    pub iops: Bucket,
+    pub throughput: Bucket,
+}
+
+impl Credits {
+    pub fn new(max_iops: Iops, max_bandwidth: BytesPerSecond, burst: &Burst) -> Self {
+        let duration = Duration::from_secs(burst.duration_secs);
+        Credits {
+            iops: Bucket::new(max_iops.0, burst.max_iops.0, duration),
+            throughput: Bucket::new(max_bandwidth.0, burst.max_bandwidth.0, duration),
+        }
+    }
+
+    pub fn update(&mut self, iops: Iops, throughput: BytesPerSecond, elapsed: Duration) {
+        self.iops.update(iops.0, elapsed);
+        self.throughput.update(throughput.0, elapsed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 100 baseline and 300 burst for 10s holds 2000 credits.
+    fn bucket() -> Bucket {
+        Bucket::new(100.0, 300.0, Duration::from_secs(10))
+    }
+
+    #[test]
+    fn accrues_below_baseline_up_to_capacity() {
+        let mut bucket = bucket();
+        assert_eq!(bucket.credits(), 2000.0);
+
+        bucket.update(300.0, Duration::from_secs(5));
+        assert_eq!(bucket.credits(), 1000.0);
+
+        bucket.update(0.0, Duration::from_secs(5));
+        assert_eq!(bucket.credits(), 1500.0);
+
+        bucket.update(0.0, Duration::from_secs(100));
+        assert_eq!(bucket.credits(), 2000.0);
+    }
+
+    #[test]
+    fn spends_above_baseline_at_most_the_burst_limit() {
+        let mut bucket = bucket();
+        bucket.update(200.0, Duration::from_secs(5));
+        assert_eq!(bucket.credits(), 1500.0);
+
+        // usage above the burst limit is throttled to it.
+        bucket.update(1000.0, Duration::from_secs(5));
+        assert_eq!(bucket.credits(), 500.0);
+
+        bucket.update(1000.0, Duration::from_secs(5));
+        assert_eq!(bucket.credits(), 0.0);
+    }
+
+    #[test]
+    fn seconds_remaining_when_bursting_and_idle() {
+        let bucket = bucket();
+        assert_eq!(bucket.seconds_remaining(200.0), 20.0);
+        assert_eq!(bucket.seconds_remaining(1000.0), 10.0);
+        // within the baseline, at the burst limit.
+        assert_eq!(bucket.seconds_remaining(50.0), 10.0);
+    }
+
+    #[test]
+    fn stops_bursting_without_credits() {
+        let mut bucket = bucket();
+        assert!(bucket.is_bursting(200.0));
+        assert!(!bucket.is_bursting(100.0));
+
+        bucket.update(300.0, Duration::from_secs(10));
+        assert_eq!(bucket.credits(), 0.0);
+        assert!(!bucket.is_bursting(200.0));
+        assert_eq!(bucket.seconds_remaining(200.0), 0.0);
+    }
+}
@@ -0,0 +1,378 @@
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::time::Duration;
+
+use super::topology::Class;
+
+/// Config holds daemon settings read from the environment at startup.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Config {
+    pub collector: Collector,
+    pub sample_interval: Duration,
+    pub windows: Vec<Duration>,
+    pub microburst_interval: Option<Duration>,
+    pub io_units: IoUnits,
+    pub arm_disk_lookup: bool,
+    pub device_filter: DeviceFilter,
+    pub mountinfo_path: String,
+    pub volume_refresh_interval: Option<Duration>,
+    pub node_name: String,
+}
+
+/// DeviceFilter selects which devices are collected. A device is
+/// collected when it matches any include rule (or there are none) and
+/// no exclude rule.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceFilter {
+    pub include: Vec<DeviceRule>,
+    pub exclude: Vec<DeviceRule>,
+}
+
+impl DeviceFilter {
+    pub fn allows(&self, label: &str, class: Class) -> bool {
+        let matches = |rule: &DeviceRule| rule.matches(label, class);
+        (self.include.is_empty() || self.include.iter().any(matches))
+            && !self.exclude.iter().any(matches)
+    }
+}
+
+/// DeviceRule matches devices by class, e.g. "class:loop", or by a glob
+/// on the device name with or without the /dev/ prefix, e.g. "sd*".
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceRule {
+    Class(String),
+    Glob(String),
+}
+
+impl DeviceRule {
+    fn matches(&self, label: &str, class: Class) -> bool {
+        match self {
+            DeviceRule::Class(name) => class.as_str() == name,
+            DeviceRule::Glob(pattern) => {
+                glob_match(pattern, label) || glob_match(pattern, label.trim_start_matches("/dev/"))
+            }
+        }
+    }
+}
+
+/// IoUnits holds the IO size in KiB which Azure meters as a single
+/// operation, by storage account type. Larger requests count as
+/// multiple IOPS against the disk and VM caps.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IoUnits {
+    pub default: u64,
+    pub storage_account_types: HashMap<String, u64>,
+}
+
+impl IoUnits {
+    pub fn size_kib(&self, storage_account_type: &str) -> u64 {
+        self.storage_account_types
+            .get(storage_account_type)
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// Collector selects how per-device statistics are gathered.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Collector {
+    /// Read /proc/diskstats directly. This is the default.
+    Diskstats,
+    /// Shell out to /sysstat/iostat.
+    Iostat,
+}
+
+pub fn new() -> Result<Config> {
+    let collector = match env::var("THROTTLED_COLLECTOR") {
+        Err(_) => Collector::Diskstats,
+        Ok(value) => match value.as_str() {
+            "diskstats" => Collector::Diskstats,
+            "iostat" => Collector::Iostat,
+            other => {
+                return Err(eyre!(
+                    "unknown collector {:?}, expected \"diskstats\" or \"iostat\"",
+                    other
+                ))
+            }
+        },
+    };
+
+    let sample_interval = match env::var("THROTTLED_SAMPLE_INTERVAL") {
+        Err(_) => Duration::from_secs(5),
+        Ok(value) => parse_duration(&value)?,
+    };
+
+    if sample_interval == Duration::from_secs(0) {
+        return Err(eyre!("THROTTLED_SAMPLE_INTERVAL must be greater than zero"));
+    }
+
+    let windows = match env::var("THROTTLED_WINDOWS") {
+        Err(_) => vec![
+            Duration::from_secs(10),
+            Duration::from_secs(60),
+            Duration::from_secs(300),
+        ],
+        Ok(value) => value
+            .split(',')
+            .filter(|window| !window.trim().is_empty())
+            .map(parse_duration)
+            .collect::<Result<Vec<Duration>>>()?,
+    };
+
+    // windows aggregate the ratios of whole samples, so a window needs at
+    // least two of them to say anything the latest sample doesn't.
+    if let Some(window) = windows.iter().find(|window| **window < sample_interval * 2) {
+        return Err(eyre!(
+            "THROTTLED_WINDOWS entry {} is shorter than twice THROTTLED_SAMPLE_INTERVAL {}",
+            format_duration(window),
+            format_duration(&sample_interval)
+        ));
+    }
+
+    let microburst_interval = match env::var("THROTTLED_MICROBURST_INTERVAL") {
+        Err(_) => None,
+        Ok(value) => Some(parse_duration(&value)?),
+    };
+
+    if microburst_interval == Some(Duration::from_secs(0)) {
+        return Err(eyre!(
+            "THROTTLED_MICROBURST_INTERVAL must be greater than zero"
+        ));
+    }
+
+    let io_units = match env::var("THROTTLED_IO_UNIT_KIB") {
+        Err(_) => parse_io_units("256")?,
+        Ok(value) => parse_io_units(&value)?,
+    };
+
+    let arm_disk_lookup = match env::var("THROTTLED_ARM_DISK_LOOKUP") {
+        Err(_) => false,
+        Ok(value) => match value.as_str() {
+            "true" => true,
+            "false" => false,
+            other => {
+                return Err(eyre!(
+                    "invalid THROTTLED_ARM_DISK_LOOKUP {:?}, expected \"true\" or \"false\"",
+                    other
+                ))
+            }
+        },
+    };
+
+    let device_filter = DeviceFilter {
+        include: match env::var("THROTTLED_DEVICE_INCLUDE") {
+            Err(_) => Vec::new(),
+            Ok(value) => parse_device_rules(&value)?,
+        },
+        exclude: match env::var("THROTTLED_DEVICE_EXCLUDE") {
+            Err(_) => Vec::new(),
+            Ok(value) => parse_device_rules(&value)?,
+        },
+    };
+
+    let mountinfo_path =
+        env::var("THROTTLED_MOUNTINFO_PATH").unwrap_or_else(|_| "/proc/self/mountinfo".to_string());
+
+    let volume_refresh_interval = match env::var("THROTTLED_VOLUME_REFRESH_INTERVAL") {
+        Err(_) => None,
+        Ok(value) => Some(parse_duration(&value)?),
+    };
+
+    if volume_refresh_interval == Some(Duration::from_secs(0)) {
+        return Err(eyre!(
+            "THROTTLED_VOLUME_REFRESH_INTERVAL must be greater than zero"
+        ));
+    }
+
+    let node_name = env::var("NODE_NAME").unwrap_or_default();
+
+    if volume_refresh_interval.is_some() && node_name.is_empty() {
+        return Err(eyre!(
+            "NODE_NAME must be set when THROTTLED_VOLUME_REFRESH_INTERVAL is set"
+        ));
+    }
+
+    Ok(Config {
+        collector,
+        sample_interval,
+        windows,
+        microburst_interval,
+        io_units,
+        arm_disk_lookup,
+        device_filter,
+        mountinfo_path,
+        volume_refresh_interval,
+        node_name,
+    })
+}
+
+/// Parses comma separated device rules such as "class:loop,sd*".
+pub fn parse_device_rules(value: &str) -> Result<Vec<DeviceRule>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .map(|rule| match rule.strip_prefix("class:") {
+            Some(name) => {
+                if Class::ALL.iter().any(|class| class.as_str() == name) {
+                    Ok(DeviceRule::Class(name.to_string()))
+                } else {
+                    Err(eyre!(
+                        "unknown device class {:?}, expected one of disk, partition, loop, ram, dm or md",
+                        name
+                    ))
+                }
+            }
+            None => Ok(DeviceRule::Glob(rule.to_string())),
+        })
+        .collect()
+}
+
+/// Matches value against a glob where "*" matches any run of characters
+/// and "?" matches a single character.
+fn glob_match(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let value: Vec<char> = value.chars().collect();
+
+    // backtrack to just after the last "*" on a mismatch.
+    let (mut p, mut v) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while v < value.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == value[v]) {
+            p += 1;
+            v += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, v));
+            p += 1;
+        } else if let Some((star_p, star_v)) = star {
+            p = star_p + 1;
+            v = star_v + 1;
+            star = Some((star_p, star_v + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Parses IO unit sizes such as "256,StandardSSD_LRS=256". Entries
+/// without a storage account type set the default.
+pub fn parse_io_units(value: &str) -> Result<IoUnits> {
+    let mut io_units = IoUnits {
+        default: 256,
+        storage_account_types: HashMap::new(),
+    };
+
+    for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (storage_account_type, size) = match entry.find('=') {
+            Some(i) => (Some(&entry[..i]), &entry[i + 1..]),
+            None => (None, entry),
+        };
+
+        let size = size
+            .trim()
+            .parse::<u64>()
+            .map_err(|e| eyre!("invalid io unit size {:?}: {}", entry, e))?;
+
+        if size == 0 {
+            return Err(eyre!("io unit size must be greater than zero: {:?}", entry));
+        }
+
+        match storage_account_type {
+            Some(storage_account_type) => {
+                io_units
+                    .storage_account_types
+                    .insert(storage_account_type.trim().to_string(), size);
+            }
+            None => io_units.default = size,
+        }
+    }
+
+    Ok(io_units)
+}
+
+/// Parses durations such as "500ms", "10s" or "5m". A bare number is
+/// interpreted as seconds.
+pub fn parse_duration(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => value.split_at(i),
+        None => (value, "s"),
+    };
+
+    let number = number
+        .parse::<u64>()
+        .map_err(|e| eyre!("invalid duration {:?}: {}", value, e))?;
+
+    match unit {
+        "ms" => Ok(Duration::from_millis(number)),
+        "s" => Ok(Duration::from_secs(number)),
+        "m" => Ok(Duration::from_secs(number * 60)),
+        _ => Err(eyre!(
+            "invalid duration {:?}, expected a unit of ms, s or m",
+            value
+        )),
+    }
+}
+
+/// Formats a duration as "500ms" or "10s" for use in metric labels.
+pub fn format_duration(duration: &Duration) -> String {
+    if duration.subsec_millis() == 0 {
+        format!("{}s", duration.as_secs())
+    } else {
+        format!("{}ms", duration.as_millis())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_match_device_names() {
+        assert!(glob_match("sd*", "sda1"));
+        assert!(!glob_match("sd*", "/dev/sda1"));
+        assert!(DeviceRule::Glob("sd*".to_string()).matches("/dev/sda1", Class::Partition));
+        assert!(glob_match("sd?", "sdb"));
+        assert!(!glob_match("sd?", "sdb1"));
+        assert!(glob_match("nvme0n1*", "nvme0n1"));
+        assert!(glob_match("nvme0n1*", "nvme0n1p2"));
+        assert!(!glob_match("loop*", "sda"));
+    }
+
+    #[test]
+    fn parse_device_rules_by_class_and_glob() {
+        assert_eq!(
+            parse_device_rules("class:loop, sd*,").unwrap(),
+            vec![
+                DeviceRule::Class("loop".to_string()),
+                DeviceRule::Glob("sd*".to_string()),
+            ]
+        );
+        assert!(parse_device_rules("class:floppy").is_err());
+    }
+
+    #[test]
+    fn parse_io_units_by_storage_account_type() {
+        let io_units = parse_io_units("128,StandardSSD_LRS=256").unwrap();
+        assert_eq!(io_units.size_kib("Premium_LRS"), 128);
+        assert_eq!(io_units.size_kib("StandardSSD_LRS"), 256);
+        assert!(parse_io_units("0").is_err());
+        assert!(parse_io_units("Premium_LRS=big").is_err());
+    }
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("10s").unwrap(), Duration::from_secs(10));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("30").unwrap(), Duration::from_secs(30));
+        assert!(parse_duration("10h").is_err());
+        assert!(parse_duration("s").is_err());
+    }
+}
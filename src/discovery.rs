@@ -0,0 +1,222 @@
+use eyre::{eyre, Context, Result};
+use relative_path::RelativePath;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const SCSI_LUN_DIR: &str = "/dev/disk/azure/scsi1";
+const ROOT_DISK_LINK: &str = "/dev/disk/azure/root";
+const RESOURCE_DISK_LINK: &str = "/dev/disk/azure/resource";
+const SCSI_DISK_CLASS_DIR: &str = "/sys/class/scsi_disk";
+const NVME_CLASS_DIR: &str = "/sys/class/nvme";
+
+// vmbus device ids of the storage controllers, as matched by the azure
+// udev rules. Gen1 vms attach the os and temp disks to dedicated
+// controllers, Gen2 vms attach them to scsi0 as lun 0 and lun 1. Data
+// disks are always on scsi1.
+const ROOT_DEVICE_ID_PREFIX: &str = "00000000-0000-";
+const RESOURCE_DEVICE_ID_PREFIX: &str = "00000000-0001-";
+const SCSI0_DEVICE_ID: &str = "{f8b3781a-1e82-4818-a1c3-63d806ec15bb}";
+const SCSI1_DEVICE_ID: &str = "{f8b3781b-1e82-4818-a1c3-63d806ec15bb}";
+
+// remote managed disks are exposed as namespaces of a single controller,
+// the os disk as namespace 1 and data disks as namespace lun + 2.
+const REMOTE_NVME_MODEL: &str = "MSFT NVMe Accelerator";
+const LOCAL_NVME_MODEL: &str = "Microsoft NVMe Direct Disk";
+
+/// Devices maps the disks reported by IMDS to the block devices they
+/// are attached as, on both SCSI and NVMe hosts.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Devices {
+    pub os_disk: Option<String>,
+    pub data_disks: HashMap<String, String>,
+    pub temp_disk: Option<String>,
+    pub local_disks: Vec<String>,
+}
+
+impl Devices {
+    pub fn data_disk(&self, lun: &str) -> Result<&str> {
+        self.data_disks
+            .get(lun)
+            .map(String::as_str)
+            .ok_or_else(|| eyre!("no device found for data disk at lun {}", lun))
+    }
+}
+
+/// Discovers attached devices. SCSI hosts are mapped through sysfs, and
+/// through the udev links Azure installs where those are present, since
+/// often only some of them are mounted into the container. NVMe hosts
+/// are mapped through the controller models in sysfs.
+pub fn discover() -> Result<Devices> {
+    let mut devices = Devices::default();
+
+    discover_sysfs(&mut devices)?;
+    discover_udev(&mut devices)?;
+    discover_nvme(&mut devices)?;
+
+    Ok(devices)
+}
+
+fn discover_udev(devices: &mut Devices) -> Result<()> {
+    if let Some(os_disk) = resolve_optional_device(ROOT_DISK_LINK)? {
+        devices.os_disk = Some(os_disk);
+    }
+    if let Some(temp_disk) = resolve_optional_device(RESOURCE_DISK_LINK)? {
+        devices.temp_disk = Some(temp_disk);
+    }
+
+    for name in list_dir(SCSI_LUN_DIR)? {
+        // skip partition links such as lun0-part1.
+        if let Some(Ok(lun)) = name.strip_prefix("lun").map(str::parse::<u64>) {
+            let device = resolve_device(&format!("{}/{}", SCSI_LUN_DIR, name))?;
+            devices.data_disks.insert(lun.to_string(), device);
+        }
+    }
+
+    Ok(())
+}
+
+/// Maps scsi disks to their role from the vmbus controller they are
+/// attached to and their host:channel:target:lun address, the same way
+/// the azure udev rules do.
+fn discover_sysfs(devices: &mut Devices) -> Result<()> {
+    for address in list_dir(SCSI_DISK_CLASS_DIR)? {
+        let lun = match address.rsplit(':').next().map(str::parse::<u64>) {
+            Some(Ok(lun)) => lun,
+            _ => continue,
+        };
+
+        let device_dir = format!("{}/{}/device", SCSI_DISK_CLASS_DIR, address);
+        let device = match list_dir(&format!("{}/block", device_dir))?.pop() {
+            Some(name) => format!("/dev/{}", name),
+            None => continue,
+        };
+
+        let device_id = match vmbus_device_id(&device_dir)? {
+            Some(device_id) => device_id,
+            None => continue,
+        };
+        let instance = device_id.get(1..).unwrap_or_default();
+
+        if instance.starts_with(ROOT_DEVICE_ID_PREFIX) {
+            devices.os_disk = Some(device);
+        } else if instance.starts_with(RESOURCE_DEVICE_ID_PREFIX) {
+            devices.temp_disk = Some(device);
+        } else if device_id == SCSI0_DEVICE_ID {
+            match lun {
+                0 => devices.os_disk = Some(device),
+                1 => devices.temp_disk = Some(device),
+                _ => (),
+            }
+        } else if device_id == SCSI1_DEVICE_ID {
+            devices.data_disks.insert(lun.to_string(), device);
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the device id of the vmbus device a scsi device sits on,
+/// found by walking up its sysfs path.
+fn vmbus_device_id(device_dir: &str) -> Result<Option<String>> {
+    let path = fs::canonicalize(device_dir)
+        .wrap_err_with(|| format!("failed to resolve {}", device_dir))?;
+
+    for dir in path.ancestors() {
+        if let Ok(device_id) = fs::read_to_string(dir.join("device_id")) {
+            return Ok(Some(device_id.trim().to_lowercase()));
+        }
+    }
+
+    Ok(None)
+}
+
+fn discover_nvme(devices: &mut Devices) -> Result<()> {
+    for controller in list_dir(NVME_CLASS_DIR)? {
+        let dir = format!("{}/{}", NVME_CLASS_DIR, controller);
+        let model = fs::read_to_string(format!("{}/model", dir))
+            .wrap_err_with(|| format!("failed to read model of {}", controller))?;
+        let model = model.trim();
+
+        let prefix = format!("{}n", controller);
+        for name in list_dir(&dir)? {
+            let nsid = match name.strip_prefix(&prefix).map(str::parse::<u64>) {
+                Some(Ok(nsid)) => nsid,
+                _ => continue,
+            };
+            let device = format!("/dev/{}", name);
+
+            if model.starts_with(REMOTE_NVME_MODEL) {
+                match nsid {
+                    1 => devices.os_disk = Some(device),
+                    n if n >= 2 => {
+                        devices.data_disks.insert((n - 2).to_string(), device);
+                    }
+                    _ => (),
+                }
+            } else if model.starts_with(LOCAL_NVME_MODEL) {
+                devices.local_disks.push(device);
+            }
+        }
+    }
+
+    devices.local_disks.sort();
+
+    Ok(())
+}
+
+/// Lists the entry names of a directory, or nothing if it doesn't exist.
+pub fn list_dir(dir: &str) -> Result<Vec<String>> {
+    let entries = match fs::read_dir(dir) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).wrap_err_with(|| format!("failed to list {}", dir)),
+        Ok(entries) => entries,
+    };
+
+    let mut names = Vec::new();
+    for entry in entries {
+        let entry = entry.wrap_err_with(|| format!("failed to list {}", dir))?;
+        if let Ok(name) = entry.file_name().into_string() {
+            names.push(name);
+        }
+    }
+
+    Ok(names)
+}
+
+/// Resolves a udev symlink which may not exist.
+fn resolve_optional_device(link: &str) -> Result<Option<String>> {
+    match fs::symlink_metadata(link) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).wrap_err_with(|| format!("failed to stat {}", link)),
+        Ok(_) => resolve_device(link).map(Some),
+    }
+}
+
+/// Resolves a udev symlink to the device file it points at, e.g.
+/// /dev/disk/azure/scsi1/lun0 to /dev/sdc.
+fn resolve_device(link: &str) -> Result<String> {
+    // TODO(ace): clean this up...maybe shell to readlink -f?
+    // normalization without following the symlink seems
+    // strangely difficult.
+    let device_file = fs::read_link(link).wrap_err_with(|| "failed to read link")?;
+
+    let parent = Path::new(link)
+        .parent()
+        .ok_or_else(|| eyre!("no parent directory for link: {}", link))?;
+
+    let device_file: PathBuf =
+        RelativePath::new(&format!("{}/{}", parent.display(), device_file.display()))
+            .normalize()
+            .to_path("/");
+
+    match device_file.to_owned().into_os_string().into_string() {
+        Err(e) => Err(eyre!(
+            "failed to convert path to friendly udev label: {:?}; err: {:?}",
+            &device_file,
+            &e,
+        )),
+        Ok(s) => Ok(s),
+    }
+}
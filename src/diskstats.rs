@@ -0,0 +1,341 @@
+use eyre::{eyre, Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::time::Instant;
+
+use super::iostat::Disk;
+
+const DISKSTATS: &str = "/proc/diskstats";
+
+// the kernel always reports sectors as 512 bytes, regardless of the
+// logical block size of the device.
+pub const SECTOR_SIZE: u64 = 512;
+
+pub fn new() -> Result<Collector> {
+    Ok(Collector {
+        previous: read()?,
+        at: Instant::now(),
+    })
+}
+
+/// Collector computes iostat-equivalent statistics from the difference
+/// between consecutive reads of /proc/diskstats.
+pub struct Collector {
+    previous: HashMap<String, Snapshot>,
+    at: Instant,
+}
+
+impl Collector {
+    /// Reads the current counters and returns per-device statistics
+    /// averaged over the time since the previous call.
+    pub fn sample(&mut self) -> Result<HashMap<String, Disk>> {
+        let current = read()?;
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.at).as_secs_f64();
+
+        let mut map: HashMap<String, Disk> = HashMap::new();
+        for (device, snapshot) in current.iter() {
+            if let Some(previous) = self.previous.get(device) {
+                map.insert(
+                    device.clone(),
+                    snapshot.delta(previous).disk(device, elapsed),
+                );
+            }
+        }
+
+        self.previous = current;
+        self.at = now;
+
+        Ok(map)
+    }
+}
+
+/// Snapshot holds the raw cumulative counters of a single line of
+/// /proc/diskstats. See Documentation/admin-guide/iostats.rst in the
+/// kernel tree for field definitions. Discard and flush fields are
+/// absent on older kernels and default to zero.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub reads_completed: u64,
+    pub reads_merged: u64,
+    pub sectors_read: u64,
+    pub read_time_ms: u64,
+    pub writes_completed: u64,
+    pub writes_merged: u64,
+    pub sectors_written: u64,
+    pub write_time_ms: u64,
+    pub in_flight: u64,
+    pub io_time_ms: u64,
+    pub weighted_io_time_ms: u64,
+    pub discards_completed: u64,
+    pub discards_merged: u64,
+    pub sectors_discarded: u64,
+    pub discard_time_ms: u64,
+    pub flushes_completed: u64,
+    pub flush_time_ms: u64,
+}
+
+impl Snapshot {
+    fn parse(fields: &[&str]) -> Result<Self> {
+        let values = fields
+            .iter()
+            .map(|field| field.parse::<u64>())
+            .collect::<Result<Vec<u64>, _>>()?;
+
+        if values.len() < 11 {
+            return Err(eyre!(
+                "expected at least 11 diskstats fields but found {}",
+                values.len()
+            ));
+        }
+
+        let get = |i: usize| values.get(i).copied().unwrap_or_default();
+
+        Ok(Snapshot {
+            reads_completed: get(0),
+            reads_merged: get(1),
+            sectors_read: get(2),
+            read_time_ms: get(3),
+            writes_completed: get(4),
+            writes_merged: get(5),
+            sectors_written: get(6),
+            write_time_ms: get(7),
+            in_flight: get(8),
+            io_time_ms: get(9),
+            weighted_io_time_ms: get(10),
+            discards_completed: get(11),
+            discards_merged: get(12),
+            sectors_discarded: get(13),
+            discard_time_ms: get(14),
+            flushes_completed: get(15),
+            flush_time_ms: get(16),
+        })
+    }
+
+    /// Returns the counter increase since previous. Counters which went
+    /// backwards (device reset or wraparound) are treated as zero.
+    pub fn delta(&self, previous: &Snapshot) -> Snapshot {
+        Snapshot {
+            reads_completed: self
+                .reads_completed
+                .saturating_sub(previous.reads_completed),
+            reads_merged: self.reads_merged.saturating_sub(previous.reads_merged),
+            sectors_read: self.sectors_read.saturating_sub(previous.sectors_read),
+            read_time_ms: self.read_time_ms.saturating_sub(previous.read_time_ms),
+            writes_completed: self
+                .writes_completed
+                .saturating_sub(previous.writes_completed),
+            writes_merged: self.writes_merged.saturating_sub(previous.writes_merged),
+            sectors_written: self
+                .sectors_written
+                .saturating_sub(previous.sectors_written),
+            write_time_ms: self.write_time_ms.saturating_sub(previous.write_time_ms),
+            in_flight: self.in_flight,
+            io_time_ms: self.io_time_ms.saturating_sub(previous.io_time_ms),
+            weighted_io_time_ms: self
+                .weighted_io_time_ms
+                .saturating_sub(previous.weighted_io_time_ms),
+            discards_completed: self
+                .discards_completed
+                .saturating_sub(previous.discards_completed),
+            discards_merged: self
+                .discards_merged
+                .saturating_sub(previous.discards_merged),
+            sectors_discarded: self
+                .sectors_discarded
+                .saturating_sub(previous.sectors_discarded),
+            discard_time_ms: self
+                .discard_time_ms
+                .saturating_sub(previous.discard_time_ms),
+            flushes_completed: self
+                .flushes_completed
+                .saturating_sub(previous.flushes_completed),
+            flush_time_ms: self.flush_time_ms.saturating_sub(previous.flush_time_ms),
+        }
+    }
+
+    /// Converts a delta over elapsed seconds into the same values
+    /// `iostat -x` would report for the interval.
+    fn disk(&self, device: &str, elapsed: f64) -> Disk {
+        let per_second = |n: f64| -> f64 {
+            if elapsed > 0.0 {
+                n / elapsed
+            } else {
+                0.0
+            }
+        };
+        let rate = |n: u64| -> f64 { per_second(n as f64) };
+        let kilobytes = |sectors: u64| -> f64 { (sectors * SECTOR_SIZE) as f64 / 1024.0 };
+        let per_request = |total: f64, requests: u64| -> f64 {
+            if requests > 0 {
+                total / requests as f64
+            } else {
+                0.0
+            }
+        };
+        let percent_merged = |merged: u64, completed: u64| -> f64 {
+            if merged + completed > 0 {
+                100.0 * merged as f64 / (merged + completed) as f64
+            } else {
+                0.0
+            }
+        };
+
+        Disk {
+            disk_device: device.trim_start_matches("/dev/").to_string(),
+            reads_per_second: rate(self.reads_completed),
+            writes_per_second: rate(self.writes_completed),
+            discards_per_second: rate(self.discards_completed),
+            flushes_per_second: rate(self.flushes_completed),
+            read_kilo_bytes_per_second: per_second(kilobytes(self.sectors_read)),
+            write_kilo_bytes_per_second: per_second(kilobytes(self.sectors_written)),
+            discard_kilo_bytes_per_second: per_second(kilobytes(self.sectors_discarded)),
+            read_requests_merged_per_second: rate(self.reads_merged),
+            write_requests_merged_per_second: rate(self.writes_merged),
+            discard_requests_merged_per_second: rate(self.discards_merged),
+            percent_read_requests_merged: percent_merged(self.reads_merged, self.reads_completed),
+            percent_write_requests_merged: percent_merged(
+                self.writes_merged,
+                self.writes_completed,
+            ),
+            percent_discard_requests_merged: percent_merged(
+                self.discards_merged,
+                self.discards_completed,
+            ),
+            read_await: per_request(self.read_time_ms as f64, self.reads_completed),
+            write_await: per_request(self.write_time_ms as f64, self.writes_completed),
+            discard_await: per_request(self.discard_time_ms as f64, self.discards_completed),
+            flush_await: per_request(self.flush_time_ms as f64, self.flushes_completed),
+            read_average_request_size: per_request(
+                kilobytes(self.sectors_read),
+                self.reads_completed,
+            ),
+            write_average_request_size: per_request(
+                kilobytes(self.sectors_written),
+                self.writes_completed,
+            ),
+            discard_average_request_size: per_request(
+                kilobytes(self.sectors_discarded),
+                self.discards_completed,
+            ),
+            average_request_size: per_second(self.weighted_io_time_ms as f64 / 1000.0),
+            util: per_second(100.0 * self.io_time_ms as f64 / 1000.0).min(100.0),
+        }
+    }
+}
+
+/// Reads /proc/diskstats into raw counter snapshots keyed by device
+/// path, e.g. /dev/sda.
+pub fn read() -> Result<HashMap<String, Snapshot>> {
+    let contents =
+        fs::read_to_string(DISKSTATS).wrap_err_with(|| format!("failed to read {}", DISKSTATS))?;
+
+    let mut map: HashMap<String, Snapshot> = HashMap::new();
+    for line in contents.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 3 {
+            continue;
+        }
+
+        let snapshot = Snapshot::parse(&fields[3..])
+            .wrap_err_with(|| format!("failed to parse diskstats line: {}", line))?;
+
+        map.insert(format!("/dev/{}", fields[2]), snapshot);
+    }
+
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an older kernel line without discard and flush fields, and a
+    // later one from a 5.5+ kernel with all 17.
+    const BEFORE: &str = "1000 100 80000 2000 500 50 40000 3000 2 4000 5000";
+    const AFTER: &str = "1500 150 120000 3000 1000 100 60000 5000 1 6000 9000 10 0 800 20 30 60";
+
+    fn snapshot(line: &str) -> Snapshot {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        Snapshot::parse(&fields).unwrap()
+    }
+
+    #[test]
+    fn parse_with_and_without_discard_and_flush_fields() {
+        let before = snapshot(BEFORE);
+        assert_eq!(before.reads_completed, 1000);
+        assert_eq!(before.weighted_io_time_ms, 5000);
+        assert_eq!(before.discards_completed, 0);
+        assert_eq!(before.flush_time_ms, 0);
+
+        let after = snapshot(AFTER);
+        assert_eq!(after.sectors_discarded, 800);
+        assert_eq!(after.flushes_completed, 30);
+        assert_eq!(after.flush_time_ms, 60);
+
+        assert!(Snapshot::parse(&["1"; 10]).is_err());
+        assert!(
+            Snapshot::parse(&["1", "2", "x", "4", "5", "6", "7", "8", "9", "10", "11"]).is_err()
+        );
+    }
+
+    #[test]
+    fn delta_saturates_on_counter_reset() {
+        let delta = snapshot(BEFORE).delta(&snapshot(AFTER));
+        assert_eq!(delta.reads_completed, 0);
+        assert_eq!(delta.sectors_written, 0);
+        assert_eq!(delta.io_time_ms, 0);
+        // in flight is a gauge, not a counter.
+        assert_eq!(delta.in_flight, 2);
+    }
+
+    #[test]
+    fn disk_matches_iostat() {
+        let disk = snapshot(AFTER)
+            .delta(&snapshot(BEFORE))
+            .disk("/dev/sdc", 2.0);
+
+        assert_eq!(disk.disk_device, "sdc");
+        assert_eq!(disk.reads_per_second, 250.0);
+        assert_eq!(disk.writes_per_second, 250.0);
+        assert_eq!(disk.discards_per_second, 5.0);
+        assert_eq!(disk.flushes_per_second, 15.0);
+        assert_eq!(disk.read_kilo_bytes_per_second, 10000.0);
+        assert_eq!(disk.write_kilo_bytes_per_second, 5000.0);
+        assert_eq!(disk.discard_kilo_bytes_per_second, 200.0);
+        assert_eq!(disk.read_requests_merged_per_second, 25.0);
+        assert!((disk.percent_read_requests_merged - 100.0 * 50.0 / 550.0).abs() < 1e-9);
+        assert_eq!(disk.percent_discard_requests_merged, 0.0);
+        assert_eq!(disk.read_await, 2.0);
+        assert_eq!(disk.write_await, 4.0);
+        assert_eq!(disk.discard_await, 2.0);
+        assert_eq!(disk.flush_await, 2.0);
+        assert_eq!(disk.read_average_request_size, 40.0);
+        assert_eq!(disk.write_average_request_size, 20.0);
+        assert_eq!(disk.discard_average_request_size, 40.0);
+        assert_eq!(disk.average_request_size, 2.0);
+        assert_eq!(disk.util, 100.0);
+    }
+
+    #[test]
+    fn disk_clamps_util_at_100() {
+        let disk = snapshot(AFTER)
+            .delta(&snapshot(BEFORE))
+            .disk("/dev/sdc", 1.0);
+        assert_eq!(disk.util, 100.0);
+        assert_eq!(disk.reads_per_second, 500.0);
+    }
+
+    #[test]
+    fn disk_without_elapsed_time_reports_no_rates() {
+        let disk = snapshot(AFTER)
+            .delta(&snapshot(BEFORE))
+            .disk("/dev/sdc", 0.0);
+        assert_eq!(disk.reads_per_second, 0.0);
+        assert_eq!(disk.read_kilo_bytes_per_second, 0.0);
+        assert_eq!(disk.average_request_size, 0.0);
+        assert_eq!(disk.util, 0.0);
+        // per request values don't depend on elapsed time.
+        assert_eq!(disk.read_await, 2.0);
+    }
+}
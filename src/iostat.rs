@@ -3,7 +3,9 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::process::Command;
 
-pub fn new() -> Result<HashMap<String, Disk>> {
+use super::units::{BytesPerSecond, Iops};
+
+pub fn new() -> Result<Sample> {
     // invoke iostat for 1, 1-second interval with detailed table output
     // and no extra summary
     let output = Command::new("/sysstat/iostat")
@@ -29,16 +31,27 @@ pub fn new() -> Result<HashMap<String, Disk>> {
         .sysstat
         .hosts
         .pop()
-        .ok_or(eyre!("no iostat hosts found"))?
+        .ok_or_else(|| eyre!("no iostat hosts found"))?
         .statistics
         .clone();
-    let stats = stats.pop().ok_or(eyre!("no iostat stats found"))?;
+    let stats = stats.pop().ok_or_else(|| eyre!("no iostat stats found"))?;
 
     for disk in stats.disk {
-        map.insert(format!("/dev/{}", &disk.disk_device), disk);
+        map.insert(format!("/dev/{}", disk.disk_device), disk);
     }
 
-    Ok(map)
+    Ok(Sample {
+        cpu: stats.avg_cpu,
+        disks: map,
+    })
+}
+
+/// Sample is a single interval of cpu and per-device statistics, with
+/// disks keyed by device path.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub cpu: AverageCpU,
+    pub disks: HashMap<String, Disk>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -130,11 +143,44 @@ pub struct Disk {
 }
 
 impl Disk {
-    pub fn iops(&self) -> f64 {
-        self.reads_per_second + self.writes_per_second
+    pub fn iops(&self) -> Iops {
+        self.read_iops() + self.write_iops()
+    }
+
+    pub fn throughput(&self) -> BytesPerSecond {
+        self.read_throughput() + self.write_throughput()
+    }
+
+    pub fn read_iops(&self) -> Iops {
+        Iops(self.reads_per_second)
+    }
+
+    pub fn write_iops(&self) -> Iops {
+        Iops(self.writes_per_second)
     }
 
-    pub fn throughput(&self) -> f64 {
-        self.read_kilo_bytes_per_second + self.write_kilo_bytes_per_second
+    pub fn read_throughput(&self) -> BytesPerSecond {
+        BytesPerSecond::from_kilobytes(self.read_kilo_bytes_per_second)
     }
+
+    pub fn write_throughput(&self) -> BytesPerSecond {
+        BytesPerSecond::from_kilobytes(self.write_kilo_bytes_per_second)
+    }
+
+    /// Returns read IOPS as Azure meters them: each request counts once
+    /// per started io unit, based on the average request size.
+    pub fn read_effective_iops(&self, io_unit_kib: u64) -> Iops {
+        self.read_iops() * io_units(self.read_average_request_size, io_unit_kib)
+    }
+
+    /// Returns write IOPS as Azure meters them.
+    pub fn write_effective_iops(&self, io_unit_kib: u64) -> Iops {
+        self.write_iops() * io_units(self.write_average_request_size, io_unit_kib)
+    }
+}
+
+/// Returns how many io units a request of the given average size in KiB
+/// is metered as.
+fn io_units(average_request_size: f64, io_unit_kib: u64) -> f64 {
+    (average_request_size / io_unit_kib as f64).ceil().max(1.0)
 }
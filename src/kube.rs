@@ -1,6 +1,8 @@
 use eyre::{Context, Result};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::fs::File;
+use std::env;
+use std::fs::{self, File};
 use std::io::Read;
 
 pub fn new() -> Result<CloudProviderConfig> {
@@ -60,3 +62,156 @@ pub struct CloudProviderConfig {
     pub provider_key_name: String,
     pub provider_key_version: String,
 }
+
+const SERVICE_ACCOUNT_DIR: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+
+/// Client is a minimal Kubernetes API client using the in-cluster
+/// service account. Projected service account tokens are rotated by
+/// kubelet, so the token is read again for every request.
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    host: String,
+    token_path: String,
+}
+
+pub fn in_cluster() -> Result<Client> {
+    let host = env::var("KUBERNETES_SERVICE_HOST")
+        .wrap_err_with(|| "KUBERNETES_SERVICE_HOST is not set, not running in a cluster")?;
+    let port = env::var("KUBERNETES_SERVICE_PORT").unwrap_or_else(|_| "443".to_string());
+
+    let ca = fs::read(format!("{}/ca.crt", SERVICE_ACCOUNT_DIR))
+        .wrap_err_with(|| "failed to read service account ca")?;
+
+    let http = reqwest::Client::builder()
+        .add_root_certificate(
+            reqwest::Certificate::from_pem(&ca)
+                .wrap_err_with(|| "failed to parse service account ca")?,
+        )
+        .build()?;
+
+    let client = Client {
+        http,
+        host: format!("https://{}:{}", host, port),
+        token_path: format!("{}/token", SERVICE_ACCOUNT_DIR),
+    };
+    client.token()?;
+
+    Ok(client)
+}
+
+impl Client {
+    pub async fn list_persistent_volumes(&self) -> Result<List<PersistentVolume>> {
+        self.get("/api/v1/persistentvolumes", &[]).await
+    }
+
+    /// Lists the pods scheduled to a node.
+    pub async fn list_pods(&self, node_name: &str) -> Result<List<Pod>> {
+        let selector = format!("spec.nodeName={}", node_name);
+        self.get("/api/v1/pods", &[("fieldSelector", &selector)])
+            .await
+    }
+
+    fn token(&self) -> Result<String> {
+        let token = fs::read_to_string(&self.token_path)
+            .wrap_err_with(|| "failed to read service account token")?;
+        Ok(token.trim().to_string())
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str, query: &[(&str, &str)]) -> Result<T> {
+        let res = self
+            .http
+            .get(&format!("{}{}", self.host, path))
+            .header("Authorization", &format!("Bearer {}", self.token()?))
+            .query(query)
+            .send()
+            .await
+            .wrap_err_with(|| format!("failed to fetch {}", path))?
+            .text()
+            .await
+            .wrap_err_with(|| format!("failed to receive {} response", path))?;
+
+        let res: T = serde_json::from_str(&res[..])
+            .wrap_err_with(|| format!("failed to parse {} from response: {}", path, res))?;
+
+        Ok(res)
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct List<T> {
+    #[serde(default)]
+    pub items: Vec<T>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(default = "Default::default")]
+pub struct ObjectMeta {
+    pub name: String,
+    pub namespace: String,
+    pub uid: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(default = "Default::default")]
+pub struct PersistentVolume {
+    pub metadata: ObjectMeta,
+    pub spec: PersistentVolumeSpec,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(default = "Default::default")]
+pub struct PersistentVolumeSpec {
+    pub claim_ref: Option<ObjectReference>,
+    pub csi: Option<CsiPersistentVolumeSource>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(default = "Default::default")]
+pub struct ObjectReference {
+    pub name: String,
+    pub namespace: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(default = "Default::default")]
+pub struct CsiPersistentVolumeSource {
+    pub driver: String,
+    pub volume_handle: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(default = "Default::default")]
+pub struct Pod {
+    pub metadata: ObjectMeta,
+    pub spec: PodSpec,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(default = "Default::default")]
+pub struct PodSpec {
+    pub volumes: Vec<Volume>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(default = "Default::default")]
+pub struct Volume {
+    pub name: String,
+    pub persistent_volume_claim: Option<PersistentVolumeClaimVolumeSource>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(default = "Default::default")]
+pub struct PersistentVolumeClaimVolumeSource {
+    pub claim_name: String,
+}
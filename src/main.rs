@@ -1,64 +1,57 @@
 use eyre::{Context, Result};
 use hyper::Server;
 
+mod burst;
+mod config;
+mod discovery;
+mod diskstats;
 mod imds;
 mod iostat;
 mod kube;
 mod metrics;
+mod microburst;
+mod mountinfo;
 mod oauth;
+mod procstat;
 mod resource;
+mod sampler;
 mod server;
+mod source;
+mod topology;
+mod units;
+mod volumes;
+mod window;
 
+use microburst::Detector;
 use oauth::OAuthResponse;
-use resource::{get_disk_sku, get_limits, get_vm_sku, list_disk_skus};
+use resource::{get_limits, get_vm_sku, list_disk_skus};
+use sampler::Sampler;
 use server::MetricService;
+use volumes::Refresher;
 
 fn main() -> Result<()> {
     smol::run(async {
+        let config = config::new()?;
         let azure_json = kube::new()?;
         let meta = imds::new().await?;
 
-        let token: OAuthResponse;
-
-        match azure_json.aad_client_id.as_str() {
-            "msi" => {
-                token = {
-                    oauth::get_msi_token(&azure_json.user_assigned_identity_id)
-                        .await
-                        .wrap_err_with(|| "failed to get msi token")?
-                }
-            }
-            _ => {
-                token = {
-                    oauth::get_sp_token(
-                        &azure_json.aad_client_id,
-                        &azure_json.aad_client_secret,
-                        &azure_json.tenant_id,
-                        "https://management.azure.com",
-                    )
-                    .await
-                    .wrap_err_with(|| "failed to get sp token")?
-                }
-            }
-        }
+        let token: OAuthResponse = match azure_json.aad_client_id.as_str() {
+            "msi" => oauth::get_msi_token(&azure_json.user_assigned_identity_id)
+                .await
+                .wrap_err_with(|| "failed to get msi token")?,
+            _ => oauth::get_sp_token(
+                &azure_json.aad_client_id,
+                &azure_json.aad_client_secret,
+                &azure_json.tenant_id,
+                "https://management.azure.com",
+            )
+            .await
+            .wrap_err_with(|| "failed to get sp token")?,
+        };
 
         let vm_size = meta.compute.vm_size;
         let location = azure_json.location;
 
-        let os_disk_size = meta
-            .compute
-            .storage_profile
-            .os_disk
-            .disk_size_gb
-            .parse::<u64>()?;
-
-        let os_disk_storage_type = meta
-            .compute
-            .storage_profile
-            .os_disk
-            .managed_disk
-            .storage_account_type;
-
         let vm_limit = get_vm_sku(
             &token.access_token,
             &azure_json.subscription_id,
@@ -69,24 +62,55 @@ fn main() -> Result<()> {
 
         let disk_skus =
             list_disk_skus(&token.access_token, &azure_json.subscription_id, &location).await?;
-        let os_disk_sku = get_disk_sku(&disk_skus, &os_disk_size, &os_disk_storage_type)?;
-        let data_disks = meta.compute.storage_profile.data_disks;
-        let disk_limits = get_limits(os_disk_sku, &data_disks, disk_skus)?;
+        let storage_profile = meta.compute.storage_profile;
+        let devices = discovery::discover()?;
+        let disk_limits = get_limits(
+            &token.access_token,
+            config.arm_disk_lookup,
+            &vm_limit,
+            &devices,
+            &storage_profile.os_disk,
+            &storage_profile.data_disks,
+            disk_skus,
+        )
+        .await?;
 
-        let log_limits = resource::Limits {
-            virtual_machine: vm_limit.clone(),
-            disks: disk_limits.clone(),
+        let limits = resource::Limits {
+            virtual_machine: vm_limit,
+            disks: disk_limits,
         };
 
-        println!("{}", serde_json::to_string_pretty(&log_limits)?);
+        println!("{}", serde_json::to_string_pretty(&limits)?);
 
         let metrics = metrics::Tracker::new()?;
+        metrics.set_limits(&limits);
+        let source = source::new(config.collector)?;
+
+        if let Some(interval) = config.microburst_interval {
+            Detector::new(
+                metrics.clone(),
+                limits.clone(),
+                interval,
+                config.sample_interval,
+                config.device_filter.clone(),
+            )?
+            .spawn();
+        }
 
-        let metrics_server = MetricService {
-            metrics,
-            vm_limit,
-            disk_limits,
-        };
+        if let Some(interval) = config.volume_refresh_interval {
+            let refresher = Refresher::new(
+                metrics.clone(),
+                config.node_name.clone(),
+                limits.disks.clone(),
+                config.mountinfo_path.clone(),
+                interval,
+            )?;
+            smol::Task::spawn(refresher.run()).detach();
+        }
+
+        Sampler::new(metrics.clone(), source, limits, &config).spawn();
+
+        let metrics_server = MetricService { metrics };
 
         let addr = ([0, 0, 0, 0], 8080).into();
 
@@ -1,5 +1,18 @@
 use eyre::Result;
-use prometheus::{GaugeVec, Opts, Registry};
+use prometheus::{CounterVec, Gauge, GaugeVec, IntCounterVec, Opts, Registry};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use super::burst::Credits;
+use super::diskstats::{Snapshot, SECTOR_SIZE};
+use super::iostat;
+use super::mountinfo::Mount;
+use super::resource::Limits;
+use super::topology::Device;
+use super::units::{BytesPerSecond, Iops};
+use super::volumes::Volume;
+use super::window::Aggregate;
 
 #[derive(Clone)]
 pub struct Tracker {
@@ -8,6 +21,51 @@ pub struct Tracker {
     pub throughput_ratio_gauge: GaugeVec,
     pub iops_gauge: GaugeVec,
     pub throughput_gauge: GaugeVec,
+    pub iops_ratio_window_gauge: GaugeVec,
+    pub throughput_ratio_window_gauge: GaugeVec,
+    pub effective_iops_gauge: GaugeVec,
+    pub effective_iops_ratio_gauge: GaugeVec,
+    pub iops_by_direction_gauge: GaugeVec,
+    pub throughput_by_direction_gauge: GaugeVec,
+    pub iops_ratio_by_direction_gauge: GaugeVec,
+    pub throughput_ratio_by_direction_gauge: GaugeVec,
+    pub await_gauge: GaugeVec,
+    pub average_request_size_gauge: GaugeVec,
+    pub merged_requests_gauge: GaugeVec,
+    pub merged_requests_percent_gauge: GaugeVec,
+    pub queue_size_gauge: GaugeVec,
+    pub utilization_gauge: GaugeVec,
+    pub cpu_iowait_gauge: Gauge,
+    pub cpu_steal_gauge: Gauge,
+    pub sample_age_gauge: Gauge,
+    pub microburst_peak_iops_gauge: GaugeVec,
+    pub microburst_peak_throughput_gauge: GaugeVec,
+    pub microburst_iops_over_limit_counter: IntCounterVec,
+    pub microburst_throughput_over_limit_counter: IntCounterVec,
+    pub reads_completed_counter: IntCounterVec,
+    pub writes_completed_counter: IntCounterVec,
+    pub read_bytes_counter: IntCounterVec,
+    pub written_bytes_counter: IntCounterVec,
+    pub io_time_counter: CounterVec,
+    pub disk_max_iops_gauge: GaugeVec,
+    pub disk_max_bandwidth_gauge: GaugeVec,
+    pub vm_max_iops_gauge: Gauge,
+    pub vm_max_bandwidth_gauge: Gauge,
+    pub vm_max_cached_iops_gauge: Gauge,
+    pub vm_max_cached_bandwidth_gauge: Gauge,
+    pub disk_info_gauge: GaugeVec,
+    pub device_info_gauge: GaugeVec,
+    pub mount_info_gauge: GaugeVec,
+    pub volume_info_gauge: GaugeVec,
+    pub volume_iops_ratio_gauge: GaugeVec,
+    pub volume_throughput_ratio_gauge: GaugeVec,
+    pub burst_iops_credits_gauge: GaugeVec,
+    pub burst_throughput_credits_gauge: GaugeVec,
+    pub burst_iops_seconds_remaining_gauge: GaugeVec,
+    pub burst_throughput_seconds_remaining_gauge: GaugeVec,
+    pub burst_active_gauge: GaugeVec,
+    last_sample: Arc<Mutex<Option<Instant>>>,
+    volumes: Arc<Mutex<HashMap<String, Volume>>>,
 }
 
 impl Tracker {
@@ -15,6 +73,8 @@ impl Tracker {
         let registry = Registry::new();
 
         let labels = ["device"];
+        let window_labels = ["device", "window", "aggregate"];
+        let direction_labels = ["device", "direction"];
 
         let iops_ratio_gauge_opts = Opts::new(
             "iops_ratio",
@@ -42,15 +102,370 @@ impl Tracker {
             "Gauge counting point-in-time throughput in bytes for a given device or host",
         );
 
+        let effective_iops_opts = Opts::new(
+            "effective_iops",
+            "Gauge counting point-in-time IOPS as Azure meters them for a given device or host, \
+            where requests larger than the io unit size count as multiple operations",
+        );
+
+        let effective_iops_ratio_opts = Opts::new(
+            "effective_iops_ratio",
+            "Gauge representing percentage of iops limit saturated for a given device or host, \
+            counting requests larger than the io unit size as multiple operations.",
+        );
+
+        let iops_by_direction_opts = Opts::new(
+            "iops_by_direction",
+            "Gauge counting point-in-time read or write IOPS for a given device or host",
+        );
+
+        let throughput_by_direction_opts = Opts::new(
+            "throughput_bytes_by_direction",
+            "Gauge counting point-in-time read or write throughput in bytes for a given device or host",
+        );
+
+        let iops_ratio_by_direction_opts = Opts::new(
+            "iops_ratio_by_direction",
+            "Gauge representing percentage of iops limit saturated by reads or writes \
+            for a given device or host.",
+        );
+
+        let throughput_ratio_by_direction_opts = Opts::new(
+            "throughput_ratio_by_direction",
+            "Gauge representing percentage of bandwidth limit saturated by reads or writes \
+            for a given device or host.",
+        );
+
+        let await_opts = Opts::new(
+            "await_milliseconds",
+            "Gauge representing the average time in milliseconds for requests issued \
+            to a given device to be served, by direction",
+        );
+
+        let average_request_size_opts = Opts::new(
+            "average_request_size_kilobytes",
+            "Gauge representing the average size in kilobytes of requests issued \
+            to a given device, by direction",
+        );
+
+        let merged_requests_opts = Opts::new(
+            "merged_requests_per_second",
+            "Gauge representing requests merged per second before being queued \
+            to a given device, by direction",
+        );
+
+        let merged_requests_percent_opts = Opts::new(
+            "merged_requests_percent",
+            "Gauge representing the percentage of requests merged before being queued \
+            to a given device, by direction",
+        );
+
+        let queue_size_opts = Opts::new(
+            "queue_size",
+            "Gauge representing the average queue length of requests issued to a given device",
+        );
+
+        let utilization_opts = Opts::new(
+            "utilization_percent",
+            "Gauge representing the percentage of elapsed time during which I/O requests \
+            were issued to a given device",
+        );
+
+        let cpu_iowait_opts = Opts::new(
+            "cpu_iowait_percent",
+            "Gauge representing the percentage of cpu time spent idle with outstanding disk I/O",
+        );
+
+        let cpu_steal_opts = Opts::new(
+            "cpu_steal_percent",
+            "Gauge representing the percentage of cpu time stolen by the hypervisor",
+        );
+
+        let iops_ratio_window_opts = Opts::new(
+            "iops_ratio_window",
+            "Gauge representing the avg, p95 or max of iops_ratio over a rolling window \
+            for a given device or host.",
+        );
+
+        let throughput_ratio_window_opts = Opts::new(
+            "throughput_ratio_window",
+            "Gauge representing the avg, p95 or max of throughput_ratio over a rolling window \
+            for a given device or host.",
+        );
+
+        let sample_age_opts = Opts::new(
+            "sample_age_seconds",
+            "Gauge representing seconds elapsed since the last successful sample at scrape time",
+        );
+
+        let microburst_peak_iops_opts = Opts::new(
+            "microburst_peak_iops",
+            "Gauge representing the highest IOPS observed in a single microburst interval \
+            during the last sample interval for a given device or host",
+        );
+
+        let microburst_peak_throughput_opts = Opts::new(
+            "microburst_peak_throughput_bytes",
+            "Gauge representing the highest throughput observed in a single microburst interval \
+            during the last sample interval for a given device or host",
+        );
+
+        let microburst_iops_over_limit_opts = Opts::new(
+            "microburst_iops_over_limit_total",
+            "Counter of microburst intervals in which IOPS exceeded the sku limit \
+            for a given device or host",
+        );
+
+        let microburst_throughput_over_limit_opts = Opts::new(
+            "microburst_throughput_over_limit_total",
+            "Counter of microburst intervals in which throughput exceeded the sku limit \
+            for a given device or host",
+        );
+
+        let reads_completed_opts = Opts::new(
+            "reads_completed_total",
+            "Counter of reads completed successfully for a given device",
+        );
+
+        let writes_completed_opts = Opts::new(
+            "writes_completed_total",
+            "Counter of writes completed successfully for a given device",
+        );
+
+        let read_bytes_opts = Opts::new(
+            "read_bytes_total",
+            "Counter of bytes read for a given device",
+        );
+
+        let written_bytes_opts = Opts::new(
+            "written_bytes_total",
+            "Counter of bytes written for a given device",
+        );
+
+        let io_time_opts = Opts::new(
+            "io_time_seconds_total",
+            "Counter of seconds spent doing I/O for a given device",
+        );
+
+        let disk_max_iops_opts = Opts::new(
+            "disk_max_iops",
+            "Gauge representing the uncached sku IOPS limit for a given device",
+        );
+
+        let disk_max_bandwidth_opts = Opts::new(
+            "disk_max_bandwidth_bytes",
+            "Gauge representing the uncached sku bandwidth limit in bytes per second for a given device",
+        );
+
+        let vm_max_iops_opts = Opts::new(
+            "vm_max_iops",
+            "Gauge representing the uncached sku IOPS limit for the virtual machine",
+        );
+
+        let vm_max_bandwidth_opts = Opts::new(
+            "vm_max_bandwidth_bytes",
+            "Gauge representing the uncached sku bandwidth limit in bytes per second \
+            for the virtual machine",
+        );
+
+        let vm_max_cached_iops_opts = Opts::new(
+            "vm_max_cached_iops",
+            "Gauge representing the combined cached and temp disk IOPS limit for the virtual machine",
+        );
+
+        let vm_max_cached_bandwidth_opts = Opts::new(
+            "vm_max_cached_bandwidth_bytes",
+            "Gauge representing the combined cached and temp disk bandwidth limit \
+            in bytes per second for the virtual machine",
+        );
+
+        let burst_iops_credits_opts = Opts::new(
+            "burst_iops_credits",
+            "Gauge estimating the IO burst credits remaining for a given device or host",
+        );
+
+        let burst_throughput_credits_opts = Opts::new(
+            "burst_throughput_credits_bytes",
+            "Gauge estimating the throughput burst credits in bytes remaining for a given device or host",
+        );
+
+        let burst_iops_seconds_remaining_opts = Opts::new(
+            "burst_iops_seconds_remaining",
+            "Gauge estimating the seconds until IO burst credits are exhausted at current usage, \
+            or at the burst limit when not bursting, for a given device or host",
+        );
+
+        let burst_throughput_seconds_remaining_opts = Opts::new(
+            "burst_throughput_seconds_remaining",
+            "Gauge estimating the seconds until throughput burst credits are exhausted at current usage, \
+            or at the burst limit when not bursting, for a given device or host",
+        );
+
+        let burst_active_opts = Opts::new(
+            "burst_active",
+            "Gauge which is 1 while a given device or host is above its baseline iops or bandwidth \
+            and estimated to be spending burst credits",
+        );
+
+        let device_info_opts = Opts::new(
+            "device_info",
+            "Gauge with a constant value of 1 labelled with the class of a given device, \
+            and the disk it belongs to for partitions",
+        );
+
+        let mount_info_opts = Opts::new(
+            "mount_info",
+            "Gauge with a constant value of 1 labelled with a mountpoint of a given device \
+            and its filesystem type",
+        );
+
+        let volume_info_opts = Opts::new(
+            "volume_info",
+            "Gauge with a constant value of 1 labelled with the Kubernetes persistent volume \
+            backed by a given device, its claim, and a pod using it",
+        );
+
+        let volume_iops_ratio_opts = Opts::new(
+            "volume_iops_ratio",
+            "Gauge representing percentage of iops limit saturated for the device backing \
+            a given Kubernetes persistent volume.",
+        );
+
+        let volume_throughput_ratio_opts = Opts::new(
+            "volume_throughput_ratio",
+            "Gauge representing percentage of bandwidth limit saturated for the device backing \
+            a given Kubernetes persistent volume.",
+        );
+
+        let disk_info_opts = Opts::new(
+            "disk_info",
+            "Gauge with a constant value of 1 labelled with the Azure disk attached as a given device",
+        );
+
         let iops_ratio_gauge = GaugeVec::new(iops_ratio_gauge_opts, &labels)?;
         let throughput_ratio_gauge = GaugeVec::new(throughput_ratios_opts, &labels)?;
         let iops_gauge = GaugeVec::new(iops_gauge_opts, &labels)?;
         let throughput_gauge = GaugeVec::new(throughput_opts, &labels)?;
+        let reads_completed_counter = IntCounterVec::new(reads_completed_opts, &labels)?;
+        let writes_completed_counter = IntCounterVec::new(writes_completed_opts, &labels)?;
+        let read_bytes_counter = IntCounterVec::new(read_bytes_opts, &labels)?;
+        let written_bytes_counter = IntCounterVec::new(written_bytes_opts, &labels)?;
+        let io_time_counter = CounterVec::new(io_time_opts, &labels)?;
+        let disk_max_iops_gauge = GaugeVec::new(disk_max_iops_opts, &labels)?;
+        let disk_max_bandwidth_gauge = GaugeVec::new(disk_max_bandwidth_opts, &labels)?;
+        let vm_max_iops_gauge = Gauge::with_opts(vm_max_iops_opts)?;
+        let vm_max_bandwidth_gauge = Gauge::with_opts(vm_max_bandwidth_opts)?;
+        let vm_max_cached_iops_gauge = Gauge::with_opts(vm_max_cached_iops_opts)?;
+        let vm_max_cached_bandwidth_gauge = Gauge::with_opts(vm_max_cached_bandwidth_opts)?;
+        let burst_iops_credits_gauge = GaugeVec::new(burst_iops_credits_opts, &labels)?;
+        let burst_throughput_credits_gauge = GaugeVec::new(burst_throughput_credits_opts, &labels)?;
+        let burst_iops_seconds_remaining_gauge =
+            GaugeVec::new(burst_iops_seconds_remaining_opts, &labels)?;
+        let burst_throughput_seconds_remaining_gauge =
+            GaugeVec::new(burst_throughput_seconds_remaining_opts, &labels)?;
+        let burst_active_gauge = GaugeVec::new(burst_active_opts, &labels)?;
+        let device_info_gauge = GaugeVec::new(device_info_opts, &["device", "class", "parent"])?;
+        let mount_info_gauge = GaugeVec::new(mount_info_opts, &["device", "mountpoint", "fstype"])?;
+        let volume_labels = ["device", "persistent_volume", "namespace", "claim"];
+        let volume_info_gauge = GaugeVec::new(
+            volume_info_opts,
+            &["device", "persistent_volume", "namespace", "claim", "pod"],
+        )?;
+        let volume_iops_ratio_gauge = GaugeVec::new(volume_iops_ratio_opts, &volume_labels)?;
+        let volume_throughput_ratio_gauge =
+            GaugeVec::new(volume_throughput_ratio_opts, &volume_labels)?;
+        let disk_info_gauge = GaugeVec::new(
+            disk_info_opts,
+            &[
+                "device",
+                "lun",
+                "name",
+                "storage_account_type",
+                "caching",
+                "size_gb",
+                "tier",
+                "kind",
+                "bursting_enabled",
+            ],
+        )?;
+        let iops_ratio_window_gauge = GaugeVec::new(iops_ratio_window_opts, &window_labels)?;
+        let throughput_ratio_window_gauge =
+            GaugeVec::new(throughput_ratio_window_opts, &window_labels)?;
+        let effective_iops_gauge = GaugeVec::new(effective_iops_opts, &labels)?;
+        let effective_iops_ratio_gauge = GaugeVec::new(effective_iops_ratio_opts, &labels)?;
+        let iops_by_direction_gauge = GaugeVec::new(iops_by_direction_opts, &direction_labels)?;
+        let throughput_by_direction_gauge =
+            GaugeVec::new(throughput_by_direction_opts, &direction_labels)?;
+        let iops_ratio_by_direction_gauge =
+            GaugeVec::new(iops_ratio_by_direction_opts, &direction_labels)?;
+        let throughput_ratio_by_direction_gauge =
+            GaugeVec::new(throughput_ratio_by_direction_opts, &direction_labels)?;
+        let await_gauge = GaugeVec::new(await_opts, &direction_labels)?;
+        let average_request_size_gauge =
+            GaugeVec::new(average_request_size_opts, &direction_labels)?;
+        let merged_requests_gauge = GaugeVec::new(merged_requests_opts, &direction_labels)?;
+        let merged_requests_percent_gauge =
+            GaugeVec::new(merged_requests_percent_opts, &direction_labels)?;
+        let queue_size_gauge = GaugeVec::new(queue_size_opts, &labels)?;
+        let utilization_gauge = GaugeVec::new(utilization_opts, &labels)?;
+        let cpu_iowait_gauge = Gauge::with_opts(cpu_iowait_opts)?;
+        let cpu_steal_gauge = Gauge::with_opts(cpu_steal_opts)?;
+        let sample_age_gauge = Gauge::with_opts(sample_age_opts)?;
+        let microburst_peak_iops_gauge = GaugeVec::new(microburst_peak_iops_opts, &labels)?;
+        let microburst_peak_throughput_gauge =
+            GaugeVec::new(microburst_peak_throughput_opts, &labels)?;
+        let microburst_iops_over_limit_counter =
+            IntCounterVec::new(microburst_iops_over_limit_opts, &labels)?;
+        let microburst_throughput_over_limit_counter =
+            IntCounterVec::new(microburst_throughput_over_limit_opts, &labels)?;
 
         registry.register(Box::new(iops_gauge.clone()))?;
         registry.register(Box::new(throughput_gauge.clone()))?;
         registry.register(Box::new(iops_ratio_gauge.clone()))?;
         registry.register(Box::new(throughput_ratio_gauge.clone()))?;
+        registry.register(Box::new(reads_completed_counter.clone()))?;
+        registry.register(Box::new(writes_completed_counter.clone()))?;
+        registry.register(Box::new(read_bytes_counter.clone()))?;
+        registry.register(Box::new(written_bytes_counter.clone()))?;
+        registry.register(Box::new(io_time_counter.clone()))?;
+        registry.register(Box::new(disk_max_iops_gauge.clone()))?;
+        registry.register(Box::new(disk_max_bandwidth_gauge.clone()))?;
+        registry.register(Box::new(vm_max_iops_gauge.clone()))?;
+        registry.register(Box::new(vm_max_bandwidth_gauge.clone()))?;
+        registry.register(Box::new(vm_max_cached_iops_gauge.clone()))?;
+        registry.register(Box::new(vm_max_cached_bandwidth_gauge.clone()))?;
+        registry.register(Box::new(disk_info_gauge.clone()))?;
+        registry.register(Box::new(device_info_gauge.clone()))?;
+        registry.register(Box::new(mount_info_gauge.clone()))?;
+        registry.register(Box::new(volume_info_gauge.clone()))?;
+        registry.register(Box::new(volume_iops_ratio_gauge.clone()))?;
+        registry.register(Box::new(volume_throughput_ratio_gauge.clone()))?;
+        registry.register(Box::new(burst_iops_credits_gauge.clone()))?;
+        registry.register(Box::new(burst_throughput_credits_gauge.clone()))?;
+        registry.register(Box::new(burst_iops_seconds_remaining_gauge.clone()))?;
+        registry.register(Box::new(burst_throughput_seconds_remaining_gauge.clone()))?;
+        registry.register(Box::new(burst_active_gauge.clone()))?;
+        registry.register(Box::new(iops_ratio_window_gauge.clone()))?;
+        registry.register(Box::new(throughput_ratio_window_gauge.clone()))?;
+        registry.register(Box::new(effective_iops_gauge.clone()))?;
+        registry.register(Box::new(effective_iops_ratio_gauge.clone()))?;
+        registry.register(Box::new(iops_by_direction_gauge.clone()))?;
+        registry.register(Box::new(throughput_by_direction_gauge.clone()))?;
+        registry.register(Box::new(iops_ratio_by_direction_gauge.clone()))?;
+        registry.register(Box::new(throughput_ratio_by_direction_gauge.clone()))?;
+        registry.register(Box::new(await_gauge.clone()))?;
+        registry.register(Box::new(average_request_size_gauge.clone()))?;
+        registry.register(Box::new(merged_requests_gauge.clone()))?;
+        registry.register(Box::new(merged_requests_percent_gauge.clone()))?;
+        registry.register(Box::new(queue_size_gauge.clone()))?;
+        registry.register(Box::new(utilization_gauge.clone()))?;
+        registry.register(Box::new(cpu_iowait_gauge.clone()))?;
+        registry.register(Box::new(cpu_steal_gauge.clone()))?;
+        registry.register(Box::new(sample_age_gauge.clone()))?;
+        registry.register(Box::new(microburst_peak_iops_gauge.clone()))?;
+        registry.register(Box::new(microburst_peak_throughput_gauge.clone()))?;
+        registry.register(Box::new(microburst_iops_over_limit_counter.clone()))?;
+        registry.register(Box::new(microburst_throughput_over_limit_counter.clone()))?;
 
         Ok(Self {
             registry,
@@ -58,24 +473,392 @@ impl Tracker {
             throughput_ratio_gauge,
             iops_gauge,
             throughput_gauge,
+            iops_ratio_window_gauge,
+            throughput_ratio_window_gauge,
+            effective_iops_gauge,
+            effective_iops_ratio_gauge,
+            iops_by_direction_gauge,
+            throughput_by_direction_gauge,
+            iops_ratio_by_direction_gauge,
+            throughput_ratio_by_direction_gauge,
+            await_gauge,
+            average_request_size_gauge,
+            merged_requests_gauge,
+            merged_requests_percent_gauge,
+            queue_size_gauge,
+            utilization_gauge,
+            cpu_iowait_gauge,
+            cpu_steal_gauge,
+            sample_age_gauge,
+            microburst_peak_iops_gauge,
+            microburst_peak_throughput_gauge,
+            microburst_iops_over_limit_counter,
+            microburst_throughput_over_limit_counter,
+            reads_completed_counter,
+            writes_completed_counter,
+            read_bytes_counter,
+            written_bytes_counter,
+            io_time_counter,
+            disk_max_iops_gauge,
+            disk_max_bandwidth_gauge,
+            vm_max_iops_gauge,
+            vm_max_bandwidth_gauge,
+            vm_max_cached_iops_gauge,
+            vm_max_cached_bandwidth_gauge,
+            disk_info_gauge,
+            device_info_gauge,
+            mount_info_gauge,
+            volume_info_gauge,
+            volume_iops_ratio_gauge,
+            volume_throughput_ratio_gauge,
+            burst_iops_credits_gauge,
+            burst_throughput_credits_gauge,
+            burst_iops_seconds_remaining_gauge,
+            burst_throughput_seconds_remaining_gauge,
+            burst_active_gauge,
+            last_sample: Arc::new(Mutex::new(None)),
+            volumes: Arc::new(Mutex::new(HashMap::new())),
         })
     }
 
-    pub fn set_iops(&self, label: &str, value: f64) {
-        self.iops_gauge.with_label_values(&[label]).set(value)
+    pub fn mark_sampled(&self) {
+        if let Ok(mut last_sample) = self.last_sample.lock() {
+            *last_sample = Some(Instant::now());
+        }
+    }
+
+    /// Sets the sample age gauge to the time since the last successful
+    /// sample. The gauge stays unset until the first sample completes.
+    pub fn update_sample_age(&self) {
+        if let Ok(last_sample) = self.last_sample.lock() {
+            if let Some(at) = *last_sample {
+                self.sample_age_gauge.set(at.elapsed().as_secs_f64());
+            }
+        }
+    }
+
+    pub fn set_iops(&self, label: &str, value: Iops) {
+        self.iops_gauge.with_label_values(&[label]).set(value.0)
     }
 
     pub fn set_iops_ratio(&self, label: &str, value: f64) {
-        self.iops_ratio_gauge.with_label_values(&[label]).set(value)
+        self.iops_ratio_gauge.with_label_values(&[label]).set(value);
+        self.set_volume_ratio(&self.volume_iops_ratio_gauge, label, value);
     }
 
-    pub fn set_throughput(&self, label: &str, value: f64) {
-        self.throughput_gauge.with_label_values(&[label]).set(value)
+    pub fn set_throughput(&self, label: &str, value: BytesPerSecond) {
+        self.throughput_gauge
+            .with_label_values(&[label])
+            .set(value.0)
     }
 
     pub fn set_throughput_ratio(&self, label: &str, value: f64) {
         self.throughput_ratio_gauge
+            .with_label_values(&[label])
+            .set(value);
+        self.set_volume_ratio(&self.volume_throughput_ratio_gauge, label, value);
+    }
+
+    /// Replaces the recorded volumes. Ratios of volumes which are gone
+    /// are dropped, and new ones appear with the next sample.
+    pub fn set_volumes(&self, volumes: HashMap<String, Volume>) {
+        self.volume_info_gauge.reset();
+        self.volume_iops_ratio_gauge.reset();
+        self.volume_throughput_ratio_gauge.reset();
+
+        for (device, volume) in volumes.iter() {
+            let labels = [
+                device.as_str(),
+                &volume.persistent_volume,
+                &volume.namespace,
+                &volume.claim,
+            ];
+            if volume.pods.is_empty() {
+                self.volume_info_gauge
+                    .with_label_values(&[labels[0], labels[1], labels[2], labels[3], ""])
+                    .set(1.0);
+            }
+            for pod in volume.pods.iter() {
+                self.volume_info_gauge
+                    .with_label_values(&[labels[0], labels[1], labels[2], labels[3], pod])
+                    .set(1.0);
+            }
+        }
+
+        if let Ok(mut current) = self.volumes.lock() {
+            *current = volumes;
+        }
+    }
+
+    fn set_volume_ratio(&self, gauge: &GaugeVec, label: &str, value: f64) {
+        if let Ok(volumes) = self.volumes.lock() {
+            if let Some(volume) = volumes.get(label) {
+                gauge
+                    .with_label_values(&[
+                        label,
+                        &volume.persistent_volume,
+                        &volume.namespace,
+                        &volume.claim,
+                    ])
+                    .set(value);
+            }
+        }
+    }
+
+    pub fn set_effective_iops(&self, label: &str, value: Iops) {
+        self.effective_iops_gauge
+            .with_label_values(&[label])
+            .set(value.0)
+    }
+
+    pub fn set_effective_iops_ratio(&self, label: &str, value: f64) {
+        self.effective_iops_ratio_gauge
             .with_label_values(&[label])
             .set(value)
     }
+
+    pub fn set_iops_by_direction(&self, label: &str, read: Iops, write: Iops) {
+        set_direction(&self.iops_by_direction_gauge, label, read.0, write.0)
+    }
+
+    pub fn set_throughput_by_direction(
+        &self,
+        label: &str,
+        read: BytesPerSecond,
+        write: BytesPerSecond,
+    ) {
+        set_direction(&self.throughput_by_direction_gauge, label, read.0, write.0)
+    }
+
+    pub fn set_iops_ratio_by_direction(&self, label: &str, read: f64, write: f64) {
+        set_direction(&self.iops_ratio_by_direction_gauge, label, read, write)
+    }
+
+    pub fn set_throughput_ratio_by_direction(&self, label: &str, read: f64, write: f64) {
+        set_direction(
+            &self.throughput_ratio_by_direction_gauge,
+            label,
+            read,
+            write,
+        )
+    }
+
+    /// Records the extended iostat statistics for a device: latency,
+    /// request sizes, merges, queue depth and utilization.
+    pub fn set_extended(&self, label: &str, stats: &iostat::Disk) {
+        let directions = [
+            (
+                "read",
+                stats.read_await,
+                stats.read_average_request_size,
+                stats.read_requests_merged_per_second,
+                stats.percent_read_requests_merged,
+            ),
+            (
+                "write",
+                stats.write_await,
+                stats.write_average_request_size,
+                stats.write_requests_merged_per_second,
+                stats.percent_write_requests_merged,
+            ),
+            (
+                "discard",
+                stats.discard_await,
+                stats.discard_average_request_size,
+                stats.discard_requests_merged_per_second,
+                stats.percent_discard_requests_merged,
+            ),
+        ];
+
+        for (direction, wait, size, merged, merged_percent) in directions.iter() {
+            self.await_gauge
+                .with_label_values(&[label, direction])
+                .set(*wait);
+            self.average_request_size_gauge
+                .with_label_values(&[label, direction])
+                .set(*size);
+            self.merged_requests_gauge
+                .with_label_values(&[label, direction])
+                .set(*merged);
+            self.merged_requests_percent_gauge
+                .with_label_values(&[label, direction])
+                .set(*merged_percent);
+        }
+
+        self.await_gauge
+            .with_label_values(&[label, "flush"])
+            .set(stats.flush_await);
+        self.queue_size_gauge
+            .with_label_values(&[label])
+            .set(stats.average_request_size);
+        self.utilization_gauge
+            .with_label_values(&[label])
+            .set(stats.util);
+    }
+
+    pub fn set_device_info(&self, label: &str, device: &Device) {
+        self.device_info_gauge
+            .with_label_values(&[
+                label,
+                device.class.as_str(),
+                device.parent.as_deref().unwrap_or_default(),
+            ])
+            .set(1.0);
+    }
+
+    /// Replaces the recorded mounts, so that unmounted filesystems don't
+    /// linger.
+    pub fn set_mounts(&self, mounts: &[Mount]) {
+        self.mount_info_gauge.reset();
+        for mount in mounts {
+            self.mount_info_gauge
+                .with_label_values(&[&mount.device, &mount.mountpoint, &mount.fstype])
+                .set(1.0);
+        }
+    }
+
+    pub fn set_cpu(&self, cpu: &iostat::AverageCpU) {
+        self.cpu_iowait_gauge.set(cpu.iowait);
+        self.cpu_steal_gauge.set(cpu.steal);
+    }
+
+    /// Increments the per-device counters by a diskstats delta.
+    pub fn inc_counters(&self, label: &str, delta: &Snapshot) {
+        self.reads_completed_counter
+            .with_label_values(&[label])
+            .inc_by(delta.reads_completed as i64);
+        self.writes_completed_counter
+            .with_label_values(&[label])
+            .inc_by(delta.writes_completed as i64);
+        self.read_bytes_counter
+            .with_label_values(&[label])
+            .inc_by((delta.sectors_read * SECTOR_SIZE) as i64);
+        self.written_bytes_counter
+            .with_label_values(&[label])
+            .inc_by((delta.sectors_written * SECTOR_SIZE) as i64);
+        self.io_time_counter
+            .with_label_values(&[label])
+            .inc_by(delta.io_time_ms as f64 / 1000.0);
+    }
+
+    /// Records the static sku limits so that rate() of the counters can
+    /// be divided by them in PromQL, along with the disk info labels.
+    pub fn set_limits(&self, limits: &Limits) {
+        self.vm_max_iops_gauge
+            .set(limits.virtual_machine.max_iops.0);
+        self.vm_max_bandwidth_gauge
+            .set(limits.virtual_machine.max_bandwidth.0);
+        if let Some(max_cached_iops) = limits.virtual_machine.max_cached_iops {
+            self.vm_max_cached_iops_gauge.set(max_cached_iops.0);
+        }
+        if let Some(max_cached_bandwidth) = limits.virtual_machine.max_cached_bandwidth {
+            self.vm_max_cached_bandwidth_gauge
+                .set(max_cached_bandwidth.0);
+        }
+        for (device, disk) in limits.disks.iter() {
+            self.disk_info_gauge
+                .with_label_values(&[
+                    device,
+                    disk.lun.as_deref().unwrap_or_default(),
+                    &disk.name,
+                    &disk.storage_account_type,
+                    &disk.caching,
+                    &disk.size_gb.to_string(),
+                    &disk.tier,
+                    disk.kind.as_str(),
+                    &disk.bursting_enabled.to_string(),
+                ])
+                .set(1.0);
+
+            if !disk.kind.has_limits() {
+                continue;
+            }
+
+            self.disk_max_iops_gauge
+                .with_label_values(&[device])
+                .set(disk.max_iops.0);
+            self.disk_max_bandwidth_gauge
+                .with_label_values(&[device])
+                .set(disk.max_bandwidth.0);
+        }
+    }
+
+    /// Records the estimated burst credit state at the current usage.
+    pub fn set_burst(
+        &self,
+        label: &str,
+        credits: &Credits,
+        iops: Iops,
+        throughput: BytesPerSecond,
+    ) {
+        self.burst_iops_credits_gauge
+            .with_label_values(&[label])
+            .set(credits.iops.credits());
+        self.burst_throughput_credits_gauge
+            .with_label_values(&[label])
+            .set(credits.throughput.credits());
+        self.burst_iops_seconds_remaining_gauge
+            .with_label_values(&[label])
+            .set(credits.iops.seconds_remaining(iops.0));
+        self.burst_throughput_seconds_remaining_gauge
+            .with_label_values(&[label])
+            .set(credits.throughput.seconds_remaining(throughput.0));
+
+        let active =
+            credits.iops.is_bursting(iops.0) || credits.throughput.is_bursting(throughput.0);
+        self.burst_active_gauge
+            .with_label_values(&[label])
+            .set(if active { 1.0 } else { 0.0 });
+    }
+
+    pub fn set_microburst_peak(&self, label: &str, iops: Iops, throughput: BytesPerSecond) {
+        self.microburst_peak_iops_gauge
+            .with_label_values(&[label])
+            .set(iops.0);
+        self.microburst_peak_throughput_gauge
+            .with_label_values(&[label])
+            .set(throughput.0);
+    }
+
+    pub fn inc_microburst_iops_over_limit(&self, label: &str) {
+        self.microburst_iops_over_limit_counter
+            .with_label_values(&[label])
+            .inc()
+    }
+
+    pub fn inc_microburst_throughput_over_limit(&self, label: &str) {
+        self.microburst_throughput_over_limit_counter
+            .with_label_values(&[label])
+            .inc()
+    }
+
+    pub fn set_iops_ratio_window(&self, label: &str, window: &str, aggregate: &Aggregate) {
+        set_aggregate(&self.iops_ratio_window_gauge, label, window, aggregate)
+    }
+
+    pub fn set_throughput_ratio_window(&self, label: &str, window: &str, aggregate: &Aggregate) {
+        set_aggregate(
+            &self.throughput_ratio_window_gauge,
+            label,
+            window,
+            aggregate,
+        )
+    }
+}
+
+fn set_direction(gauge: &GaugeVec, label: &str, read: f64, write: f64) {
+    gauge.with_label_values(&[label, "read"]).set(read);
+    gauge.with_label_values(&[label, "write"]).set(write);
+}
+
+fn set_aggregate(gauge: &GaugeVec, label: &str, window: &str, aggregate: &Aggregate) {
+    gauge
+        .with_label_values(&[label, window, "avg"])
+        .set(aggregate.avg);
+    gauge
+        .with_label_values(&[label, window, "p95"])
+        .set(aggregate.p95);
+    gauge
+        .with_label_values(&[label, window, "max"])
+        .set(aggregate.max);
 }
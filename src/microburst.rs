@@ -0,0 +1,141 @@
+use eyre::Result;
+use std::collections::HashMap;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::config::DeviceFilter;
+use super::diskstats;
+use super::metrics;
+use super::resource::{Disk, Kind, Limits, VirtualMachine};
+use super::topology::Classifier;
+use super::units::{BytesPerSecond, Iops};
+
+/// Detector samples /proc/diskstats at a sub-second interval to catch
+/// bursts which average out over the regular sample interval. It tracks
+/// the peak per-interval IOPS and throughput for each device and the VM
+/// total, and counts intervals which exceeded the corresponding limit.
+pub struct Detector {
+    metrics: metrics::Tracker,
+    collector: diskstats::Collector,
+    vm_limit: VirtualMachine,
+    disk_limits: HashMap<String, Disk>,
+    interval: Duration,
+    period: Duration,
+    peaks: HashMap<String, Peak>,
+    classifier: Classifier,
+    device_filter: DeviceFilter,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+struct Peak {
+    iops: Iops,
+    throughput: BytesPerSecond,
+}
+
+impl Detector {
+    /// Creates a detector sampling every interval. Peaks are published
+    /// and reset once per period.
+    pub fn new(
+        metrics: metrics::Tracker,
+        limits: Limits,
+        interval: Duration,
+        period: Duration,
+        device_filter: DeviceFilter,
+    ) -> Result<Self> {
+        Ok(Detector {
+            metrics,
+            collector: diskstats::new()?,
+            vm_limit: limits.virtual_machine,
+            disk_limits: limits.disks,
+            interval,
+            period,
+            peaks: HashMap::new(),
+            classifier: Classifier::default(),
+            device_filter,
+        })
+    }
+
+    pub fn spawn(self) -> thread::JoinHandle<()> {
+        thread::spawn(move || self.run())
+    }
+
+    fn run(mut self) {
+        let mut next = Instant::now() + self.interval;
+        let mut publish = Instant::now() + self.period;
+        loop {
+            thread::sleep(next.saturating_duration_since(Instant::now()));
+            next = (next + self.interval).max(Instant::now());
+
+            if let Err(e) = self.collect() {
+                println!("failed to collect microburst metrics: {}", e);
+            }
+
+            if Instant::now() >= publish {
+                publish = Instant::now() + self.period;
+                for (device, peak) in self.peaks.drain() {
+                    self.metrics
+                        .set_microburst_peak(&device, peak.iops, peak.throughput);
+                }
+            }
+        }
+    }
+
+    fn collect(&mut self) -> Result<()> {
+        let mut total = Peak::default();
+        for (disk, stats) in self.collector.sample()?.iter() {
+            let class = self.classifier.classify(disk).class;
+            let usage = Peak {
+                iops: stats.iops(),
+                throughput: stats.throughput(),
+            };
+
+            let limit = self.disk_limits.get(disk).cloned();
+
+            // only managed disks count towards the uncached total, not
+            // host local disks, partitions or stacked devices.
+            if matches!(&limit, Some(limit) if limit.kind == Kind::Managed) {
+                total.iops += usage.iops;
+                total.throughput += usage.throughput;
+            }
+
+            if !self.device_filter.allows(disk, class) {
+                continue;
+            }
+
+            self.record(disk, &usage);
+
+            if let Some(limit) = limit.filter(|limit| limit.kind.has_limits()) {
+                self.count(disk, &usage, limit.max_iops, limit.max_bandwidth);
+            }
+        }
+
+        self.record("TOTAL", &total);
+        self.count(
+            "TOTAL",
+            &total,
+            self.vm_limit.max_iops,
+            self.vm_limit.max_bandwidth,
+        );
+
+        Ok(())
+    }
+
+    fn record(&mut self, device: &str, usage: &Peak) {
+        let peak = self.peaks.entry(device.to_string()).or_default();
+        if usage.iops > peak.iops {
+            peak.iops = usage.iops;
+        }
+        if usage.throughput > peak.throughput {
+            peak.throughput = usage.throughput;
+        }
+    }
+
+    fn count(&self, device: &str, usage: &Peak, max_iops: Iops, max_bandwidth: BytesPerSecond) {
+        if usage.iops > max_iops {
+            self.metrics.inc_microburst_iops_over_limit(device);
+        }
+        if usage.throughput > max_bandwidth {
+            self.metrics.inc_microburst_throughput_over_limit(device);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use eyre::{eyre, Context, Result};
+use std::fs;
+
+const SYS_DEV_BLOCK_DIR: &str = "/sys/dev/block";
+
+/// Mount holds a single block device mount.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Mount {
+    pub device: String,
+    pub mountpoint: String,
+    pub fstype: String,
+}
+
+/// Reads the block device mounts from a mountinfo file, e.g.
+/// /proc/self/mountinfo. See proc(5) for the format. Mounts which aren't
+/// backed by a block device, such as tmpfs or overlay, are skipped.
+pub fn read(path: &str) -> Result<Vec<Mount>> {
+    let contents = fs::read_to_string(path).wrap_err_with(|| format!("failed to read {}", path))?;
+
+    let mut mounts = Vec::new();
+    for line in contents.lines() {
+        let mount = parse(line, SYS_DEV_BLOCK_DIR)
+            .wrap_err_with(|| format!("failed to parse mountinfo line: {}", line))?;
+        if let Some(mount) = mount {
+            mounts.push(mount);
+        }
+    }
+
+    Ok(mounts)
+}
+
+/// Parses a single mountinfo line, resolving device numbers through the
+/// /sys/dev/block links in sys_dev_block_dir.
+fn parse(line: &str, sys_dev_block_dir: &str) -> Result<Option<Mount>> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+
+    // optional fields end at a lone "-", followed by the filesystem
+    // type and mount source.
+    let separator = fields
+        .iter()
+        .position(|field| *field == "-")
+        .ok_or_else(|| eyre!("missing optional fields separator"))?;
+    if separator < 6 || fields.len() < separator + 3 {
+        return Err(eyre!("expected at least 9 mountinfo fields"));
+    }
+
+    let major_minor = fields[2];
+    let mountpoint = unescape(fields[4]);
+    let fstype = fields[separator + 1].to_string();
+    let source = fields[separator + 2];
+
+    // the mount source is free form, e.g. /dev/root, so prefer resolving
+    // the device number through sysfs.
+    let device = match fs::read_link(format!("{}/{}", sys_dev_block_dir, major_minor)) {
+        Ok(target) => match target.file_name().and_then(|name| name.to_str()) {
+            Some(name) => format!("/dev/{}", name),
+            None => return Ok(None),
+        },
+        Err(_) if source.starts_with("/dev/") => source.to_string(),
+        Err(_) => return Ok(None),
+    };
+
+    Ok(Some(Mount {
+        device,
+        mountpoint,
+        fstype,
+    }))
+}
+
+/// Reverses the octal escaping of spaces, tabs, newlines and
+/// backslashes in mountinfo paths, e.g. "\040" for a space.
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(i) = rest.find('\\') {
+        unescaped.push_str(&rest[..i]);
+        let escaped = rest.get(i + 1..i + 4).unwrap_or_default();
+        match u8::from_str_radix(escaped, 8) {
+            Ok(c) if escaped.len() == 3 => {
+                unescaped.push(c as char);
+                rest = &rest[i + 4..];
+            }
+            _ => {
+                unescaped.push('\\');
+                rest = &rest[i + 1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    const NO_SYS_DEV_BLOCK_DIR: &str = "/nonexistent/sys/dev/block";
+
+    #[test]
+    fn parse_with_optional_fields() {
+        let line = "2475 2449 8:33 / /var/lib/kubelet/pods/0d5c/volumes/kubernetes.io~csi/pvc-1/mount rw,relatime shared:1029 master:7 - ext4 /dev/sdc1 rw";
+        assert_eq!(
+            parse(line, NO_SYS_DEV_BLOCK_DIR).unwrap(),
+            Some(Mount {
+                device: "/dev/sdc1".to_string(),
+                mountpoint: "/var/lib/kubelet/pods/0d5c/volumes/kubernetes.io~csi/pvc-1/mount"
+                    .to_string(),
+                fstype: "ext4".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_without_optional_fields() {
+        let line = "29 1 8:1 / / rw,relatime - ext4 /dev/sda1 rw,discard";
+        assert_eq!(
+            parse(line, NO_SYS_DEV_BLOCK_DIR).unwrap(),
+            Some(Mount {
+                device: "/dev/sda1".to_string(),
+                mountpoint: "/".to_string(),
+                fstype: "ext4".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_resolves_device_numbers_through_sysfs() {
+        let dir = std::env::temp_dir().join(format!("throttled-mountinfo-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let link = dir.join("8:1");
+        let _ = fs::remove_file(&link);
+        symlink("../../devices/pci0000:00/host0/block/sda/sda1", &link).unwrap();
+
+        let line = "29 1 8:1 / / rw,relatime - ext4 /dev/root rw";
+        let mount = parse(line, dir.to_str().unwrap()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            mount.map(|mount| mount.device),
+            Some("/dev/sda1".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_skips_mounts_without_block_devices() {
+        let line = "22 29 0:21 / /dev/shm rw,nosuid,nodev shared:3 - tmpfs tmpfs rw";
+        assert_eq!(parse(line, NO_SYS_DEV_BLOCK_DIR).unwrap(), None);
+    }
+
+    #[test]
+    fn parse_rejects_malformed_lines() {
+        assert!(parse(
+            "29 1 8:1 / / rw,relatime ext4 /dev/sda1 rw",
+            NO_SYS_DEV_BLOCK_DIR
+        )
+        .is_err());
+        assert!(parse("29 1 8:1 / - ext4 /dev/sda1 rw", NO_SYS_DEV_BLOCK_DIR).is_err());
+    }
+
+    #[test]
+    fn parse_unescapes_mountpoints() {
+        let line = r"104 29 8:17 / /mnt/data\040disk rw,relatime shared:52 - xfs /dev/sdb1 rw";
+        let mount = parse(line, NO_SYS_DEV_BLOCK_DIR).unwrap().unwrap();
+        assert_eq!(mount.mountpoint, "/mnt/data disk");
+    }
+
+    #[test]
+    fn unescape_octal_escapes() {
+        assert_eq!(unescape(r"/mnt/a\040b\011c\012d\134e"), "/mnt/a b\tc\nd\\e");
+        assert_eq!(unescape(r"/mnt/trailing\04"), r"/mnt/trailing\04");
+        assert_eq!(unescape(r"/mnt/not\9octal"), r"/mnt/not\9octal");
+    }
+}
@@ -30,10 +30,10 @@ pub async fn get_sp_token(
     resource: &str,
 ) -> Result<OAuthResponse> {
     let params = [
-        (("client_id", client_id)),
-        (("client_secret", client_secret)),
-        (("grant_type", "client_credentials")),
-        (("resource", resource)),
+        ("client_id", client_id),
+        ("client_secret", client_secret),
+        ("grant_type", "client_credentials"),
+        ("resource", resource),
     ];
 
     let client = reqwest::Client::new();
@@ -0,0 +1,98 @@
+use eyre::{eyre, Context, Result};
+use std::fs;
+
+use super::iostat::AverageCpU;
+
+const STAT: &str = "/proc/stat";
+
+pub fn new() -> Result<Collector> {
+    Ok(Collector { previous: read()? })
+}
+
+/// Collector computes iostat-equivalent cpu utilization from the
+/// difference between consecutive reads of the aggregate cpu line in
+/// /proc/stat.
+pub struct Collector {
+    previous: Times,
+}
+
+impl Collector {
+    pub fn sample(&mut self) -> Result<AverageCpU> {
+        let current = read()?;
+        let cpu = current.percent_since(&self.previous);
+        self.previous = current;
+        Ok(cpu)
+    }
+}
+
+/// Times holds cumulative cpu time in clock ticks, in /proc/stat order.
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub struct Times {
+    pub user: u64,
+    pub nice: u64,
+    pub system: u64,
+    pub idle: u64,
+    pub iowait: u64,
+    pub irq: u64,
+    pub softirq: u64,
+    pub steal: u64,
+}
+
+impl Times {
+    fn percent_since(&self, previous: &Times) -> AverageCpU {
+        let user = self.user.saturating_sub(previous.user);
+        let nice = self.nice.saturating_sub(previous.nice);
+        let system = (self.system + self.irq + self.softirq)
+            .saturating_sub(previous.system + previous.irq + previous.softirq);
+        let idle = self.idle.saturating_sub(previous.idle);
+        let iowait = self.iowait.saturating_sub(previous.iowait);
+        let steal = self.steal.saturating_sub(previous.steal);
+
+        let total = (user + nice + system + idle + iowait + steal) as f64;
+        let percent = |ticks: u64| -> f64 {
+            if total > 0.0 {
+                100.0 * ticks as f64 / total
+            } else {
+                0.0
+            }
+        };
+
+        AverageCpU {
+            user: percent(user),
+            nice: percent(nice),
+            system: percent(system),
+            iowait: percent(iowait),
+            steal: percent(steal),
+            idle: percent(idle),
+        }
+    }
+}
+
+pub fn read() -> Result<Times> {
+    let contents = fs::read_to_string(STAT).wrap_err_with(|| format!("failed to read {}", STAT))?;
+
+    let line = contents
+        .lines()
+        .find(|line| line.starts_with("cpu "))
+        .ok_or_else(|| eyre!("no aggregate cpu line found in {}", STAT))?;
+
+    let values = line
+        .split_whitespace()
+        .skip(1)
+        .map(|field| field.parse::<u64>())
+        .collect::<Result<Vec<u64>, _>>()
+        .wrap_err_with(|| format!("failed to parse cpu line: {}", line))?;
+
+    let get = |i: usize| values.get(i).copied().unwrap_or_default();
+
+    Ok(Times {
+        user: get(0),
+        nice: get(1),
+        system: get(2),
+        idle: get(3),
+        iowait: get(4),
+        irq: get(5),
+        softirq: get(6),
+        steal: get(7),
+    })
+}
@@ -1,12 +1,13 @@
 use eyre::{eyre, Context, Error, Result};
-use relative_path::RelativePath;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::convert::TryFrom;
-use std::fs;
-use std::path::PathBuf;
 
+use super::burst;
+use super::discovery::Devices;
 use super::imds;
+use super::topology;
+use super::units::{BytesPerSecond, Iops};
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Limits {
@@ -14,47 +15,216 @@ pub struct Limits {
     pub disks: HashMap<String, Disk>,
 }
 
-pub fn get_limits(
-    os_disk: Disk,
-    data_disks: &Vec<imds::DataDisk>,
+pub async fn get_limits(
+    token: &str,
+    arm_disk_lookup: bool,
+    vm: &VirtualMachine,
+    devices: &Devices,
+    os_disk: &imds::OsDisk,
+    data_disks: &[imds::DataDisk],
     disk_skus: Vec<Disk>,
 ) -> Result<HashMap<String, Disk>> {
     let mut limits: HashMap<String, Disk> = HashMap::new();
-    limits.insert("/dev/sda".to_string(), os_disk);
+
+    let size = os_disk.disk_size_gb.parse::<u64>()?;
+    // ephemeral os disks live on the host's cache or temp storage, so
+    // they have no managed disk sku and share the cached limits.
+    let limits_for_os_disk = if os_disk.diff_disk_settings.option == "Local" {
+        get_cached_disk(vm, Kind::Ephemeral)
+    } else {
+        Some(
+            get_sized_disk(
+                token,
+                arm_disk_lookup,
+                &disk_skus,
+                &os_disk.managed_disk,
+                size,
+                &vm.location,
+            )
+            .await?,
+        )
+    };
+    match (&devices.os_disk, limits_for_os_disk) {
+        (None, _) => println!("no device found for os disk"),
+        (Some(_), None) => (),
+        (Some(device), Some(limits_for_os_disk)) => {
+            let os_disk_sku = Disk {
+                name: os_disk.name.clone(),
+                id: os_disk.managed_disk.id.clone(),
+                caching: os_disk.caching.clone(),
+                size_gb: size,
+                ..limits_for_os_disk
+            };
+            limits.insert(device.clone(), os_disk_sku);
+        }
+    }
 
     for disk in data_disks {
         let size = disk.disk_size_gb.parse::<u64>()?;
         let storage_account_type = &disk.managed_disk.storage_account_type;
-        let disk_sku = get_disk_sku(&disk_skus, &size, &storage_account_type)?;
-
-        // TODO(ace): clean this up...maybe shell to readlink -f?
-        // normalization without following the symlink seems
-        // strangely difficult.
-        let device_file = fs::read_link(format!("/dev/disk/azure/scsi1/lun{}", &disk.lun))
-            .wrap_err_with(|| "failed to read link")?;
-
-        let device_file: PathBuf =
-            RelativePath::new(&format!("/dev/disk/azure/scsi1/{}", device_file.display(),))
-                .normalize()
-                .to_path("/");
-
-        let device_file = match device_file.to_owned().into_os_string().into_string() {
-            Err(e) => {
-                return Err(eyre!(
-                    "failed to convert path to friendly udev label: {:?}; err: {:?}",
-                    &device_file,
-                    &e,
-                ))
-            }
-            Ok(s) => s,
+        let limits_for_disk = if is_provisioned(storage_account_type) {
+            get_arm_disk(token, &disk.managed_disk, &vm.location).await?
+        } else {
+            get_sized_disk(
+                token,
+                arm_disk_lookup,
+                &disk_skus,
+                &disk.managed_disk,
+                size,
+                &vm.location,
+            )
+            .await?
+        };
+        let disk_sku = Disk {
+            lun: Some(disk.lun.clone()),
+            name: disk.name.clone(),
+            id: disk.managed_disk.id.clone(),
+            caching: disk.caching.clone(),
+            size_gb: size,
+            ..limits_for_disk
         };
 
-        limits.insert(device_file, disk_sku);
+        limits.insert(devices.data_disk(&disk.lun)?.to_string(), disk_sku);
+    }
+
+    if let Some(temp_disk) = &devices.temp_disk {
+        if let Some(limits_for_temp_disk) = get_cached_disk(vm, Kind::Temporary) {
+            limits.insert(temp_disk.clone(), limits_for_temp_disk);
+        }
+    }
+
+    // local nvme disks don't count against any vm limit, and have no
+    // published limits of their own.
+    for local_disk in devices.local_disks.iter() {
+        let limits_for_local_disk = Disk {
+            location: vm.location.clone(),
+            kind: Kind::Local,
+            ..Default::default()
+        };
+        limits.insert(local_disk.clone(), limits_for_local_disk);
     }
 
+    let virtual_disks = topology::virtual_disks(&limits)?;
+    limits.extend(virtual_disks);
+
     Ok(limits)
 }
 
+/// Returns whether disks of a storage account type have independently
+/// provisioned performance rather than fixed size tiers.
+pub fn is_provisioned(storage_account_type: &str) -> bool {
+    matches!(storage_account_type, "UltraSSD_LRS" | "PremiumV2_LRS")
+}
+
+/// Returns the limits of a disk with size based tiers. With
+/// arm_disk_lookup, the disk resource is authoritative, since it reflects
+/// performance tier upgrades and bursting which the size alone doesn't.
+async fn get_sized_disk(
+    token: &str,
+    arm_disk_lookup: bool,
+    disk_skus: &[Disk],
+    managed_disk: &imds::ManagedDisk,
+    size: u64,
+    location: &str,
+) -> Result<Disk> {
+    if arm_disk_lookup {
+        match get_arm_disk(token, managed_disk, location).await {
+            Ok(disk) => return Ok(disk),
+            Err(e) => println!(
+                "failed to look up disk {}, falling back to sku matching: {}",
+                managed_disk.id, e
+            ),
+        }
+    }
+
+    get_disk_sku(disk_skus, &size, &managed_disk.storage_account_type)
+}
+
+/// Returns the limits of a disk as configured on its ARM resource. This
+/// is the only source of provisioned performance for ultra and premium
+/// v2 disks, which have no size based sku to match against.
+async fn get_arm_disk(
+    token: &str,
+    managed_disk: &imds::ManagedDisk,
+    location: &str,
+) -> Result<Disk> {
+    let properties = get_managed_disk(token, &managed_disk.id).await?.properties;
+
+    let (iops, mbps) = match (
+        properties.disk_iops_read_write,
+        properties.disk_mbps_read_write,
+    ) {
+        (Some(iops), Some(mbps)) => (iops, mbps),
+        _ => {
+            return Err(eyre!(
+                "no provisioned performance found for disk: {}",
+                &managed_disk.id,
+            ))
+        }
+    };
+
+    // on-demand bursting isn't credit based, so credits estimated
+    // against the baseline would say nothing about the real cap.
+    let tier = properties.tier.unwrap_or_default();
+    let bursting_enabled = properties.bursting_enabled.unwrap_or_default();
+    Ok(Disk {
+        location: location.to_string(),
+        storage_account_type: managed_disk.storage_account_type.clone(),
+        burst: if bursting_enabled {
+            None
+        } else {
+            burst::disk_burst(&tier)
+        },
+        tier,
+        max_iops: Iops(iops),
+        max_bandwidth: BytesPerSecond::from_megabytes(mbps),
+        bursting_enabled,
+        ..Default::default()
+    })
+}
+
+pub async fn get_managed_disk(token: &str, id: &str) -> Result<ManagedDisk> {
+    let res = reqwest::Client::new()
+        .get(&format!("https://management.azure.com{}", id))
+        .header("Authorization", &format!("Bearer {}", token))
+        .query(&[("api-version", "2020-12-01")])
+        .send()
+        .await
+        .wrap_err_with(|| format!("failed to fetch managed disk: {}", id))?
+        .text()
+        .await
+        .wrap_err_with(|| "failed to receive managed disk response")?;
+
+    let res: ManagedDisk = serde_json::from_str(&res[..])
+        .wrap_err_with(|| format!("failed to parse managed disk from response: {}", res))?;
+
+    Ok(res)
+}
+
+/// Returns the limits of a disk on host local storage, which shares the
+/// vm's combined temp and cached limits. It is only registered when the
+/// vm sku publishes those limits.
+fn get_cached_disk(vm: &VirtualMachine, kind: Kind) -> Option<Disk> {
+    let (max_iops, max_bandwidth) = match vm.max_cached_iops.zip(vm.max_cached_bandwidth) {
+        Some(limit) => limit,
+        None => {
+            println!(
+                "no cached limits for vm sku, skipping {} disk",
+                kind.as_str()
+            );
+            return None;
+        }
+    };
+
+    Some(Disk {
+        location: vm.location.clone(),
+        kind,
+        max_iops,
+        max_bandwidth,
+        ..Default::default()
+    })
+}
+
 pub async fn get_vm_sku(
     token: &str,
     subscription_id: &str,
@@ -66,20 +236,18 @@ pub async fn get_vm_sku(
         .value
         .into_iter()
         .filter(|sku| sku.resource_type == "virtualMachines")
-        .filter(|sku| sku.locations.len() > 0 && sku.locations[0] == location)
+        .filter(|sku| !sku.locations.is_empty() && sku.locations[0] == location)
         .filter(|sku| sku.name == name)
-        .map(|sku| VirtualMachine::try_from(sku))
+        .map(VirtualMachine::try_from)
         .collect::<Result<Vec<VirtualMachine>>>()?;
 
     match filtered.len() {
         1 => Ok(filtered.pop().unwrap()),
-        n => {
-            return Err(eyre!(
-                "expected single matching vm sku but found {}. matches: {:#?}",
-                &n,
-                &filtered,
-            ))
-        }
+        n => Err(eyre!(
+            "expected single matching vm sku but found {}. matches: {:#?}",
+            &n,
+            &filtered,
+        )),
     }
 }
 
@@ -93,21 +261,23 @@ pub async fn list_disk_skus(
         .value
         .into_iter()
         .filter(|sku| sku.resource_type == "disks")
-        .filter(|sku| sku.locations.len() > 0 && sku.locations[0] == location)
-        .filter(|res| res.tier != Some("Ultra".to_string())) // Need to support ultra, it has different range-based structure
-        .map(|sku| Disk::try_from(sku))
+        .filter(|sku| !sku.locations.is_empty() && sku.locations[0] == location)
+        // ultra and premium v2 skus have a range-based structure; their
+        // limits are provisioned per disk and read from arm instead.
+        .filter(|res| res.tier != Some("Ultra".to_string()) && !is_provisioned(&res.name))
+        .map(Disk::try_from)
         .collect::<Result<Vec<Disk>>>()
 }
 
-pub fn get_disk_sku(skus: &Vec<Disk>, size: &u64, storage_account_type: &str) -> Result<Disk> {
+pub fn get_disk_sku(skus: &[Disk], size: &u64, storage_account_type: &str) -> Result<Disk> {
     let mut filtered = skus
         .iter()
-        .filter(|sku| &sku.storage_account_type == storage_account_type)
+        .filter(|sku| sku.storage_account_type == storage_account_type)
         .filter(|sku| size > &sku.min_size_gb && size <= &sku.max_size_gb)
         .cloned()
         .collect::<Vec<Disk>>();
 
-    if filtered.len() < 1 {
+    if filtered.is_empty() {
         return Err(eyre!(
             "no matching sku found for disk with size: {}, storage type: {}",
             &size,
@@ -143,7 +313,7 @@ pub async fn list_skus(token: &str, subscription_id: &str) -> Result<ResourceLis
         .wrap_err_with(|| "failed to receive resource skus response")?;
 
     let res: ResourceList = serde_json::from_str(&res[..])
-        .wrap_err_with(|| format!("failed to parse resource skus from response: {}", &res))?;
+        .wrap_err_with(|| format!("failed to parse resource skus from response: {}", res))?;
 
     Ok(res)
 }
@@ -191,19 +361,51 @@ pub struct ZoneDetail {
     pub capabilities: Vec<Capability>,
 }
 
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManagedDisk {
+    pub name: String,
+    pub location: String,
+    #[serde(default)]
+    pub properties: ManagedDiskProperties,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManagedDiskProperties {
+    #[serde(rename = "diskSizeGB", default)]
+    pub disk_size_gb: Option<u64>,
+    #[serde(rename = "diskIOPSReadWrite", default)]
+    pub disk_iops_read_write: Option<f64>,
+    #[serde(rename = "diskMBpsReadWrite", default)]
+    pub disk_mbps_read_write: Option<f64>,
+    #[serde(default)]
+    pub tier: Option<String>,
+    #[serde(default)]
+    pub bursting_enabled: Option<bool>,
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct VirtualMachine {
     pub name: String,
     pub location: String,
-    pub max_iops: u64,
-    pub max_bandwidth: u64,
+    pub max_iops: Iops,
+    pub max_bandwidth: BytesPerSecond,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_cached_iops: Option<Iops>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_cached_bandwidth: Option<BytesPerSecond>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_size_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub burst: Option<Burst>,
 }
 
 impl TryFrom<Resource> for VirtualMachine {
     type Error = Error;
 
     fn try_from(value: Resource) -> Result<Self, Self::Error> {
-        if value.locations.len() < 1 {
+        if value.locations.is_empty() {
             return Err(eyre!("no locations available for provided sku"));
         }
 
@@ -217,7 +419,8 @@ impl TryFrom<Resource> for VirtualMachine {
         let max_iops = capabilities
             .get("UncachedDiskIOPS")
             .ok_or(eyre!("failed to find minimum vm sku size: {:#?}", &value))?
-            .parse::<u64>()?;
+            .parse::<f64>()
+            .map(Iops)?;
 
         // TODO(ace): for some reason SKUs API doesn't return bandwidth
         // numbers for b12ms. Everything above b8ms has the same limit,
@@ -227,48 +430,142 @@ impl TryFrom<Resource> for VirtualMachine {
         let max_bandwidth = capabilities
             .get("UncachedDiskBytesPerSecond")
             .ok_or(eyre!("failed to find maximum vm sku bandwidth"))?
-            .parse::<u64>()?;
+            .parse::<f64>()
+            .map(BytesPerSecond)?;
+
+        // cached and temp disk limits are only published for sizes
+        // which support host caching.
+        let max_cached_iops = capabilities
+            .get("CombinedTempDiskAndCachedIOPS")
+            .map(|v| v.parse::<f64>().map(Iops))
+            .transpose()?;
+
+        let max_cached_bandwidth = capabilities
+            .get("CombinedTempDiskAndCachedReadBytesPerSecond")
+            .or_else(|| capabilities.get("CombinedTempDiskAndCachedWriteBytesPerSecond"))
+            .map(|v| v.parse::<f64>().map(BytesPerSecond))
+            .transpose()?;
+
+        let cache_size_bytes = capabilities
+            .get("CachedDiskBytes")
+            .map(|v| v.parse::<u64>())
+            .transpose()?;
+
+        // the resource skus api doesn't publish burst limits, so they
+        // come from the bundled table of sizes with vm level bursting.
+        let burst = burst::vm_burst(&name);
 
         let sku = VirtualMachine {
             name,
+            burst,
             location,
             max_iops,
             max_bandwidth,
+            max_cached_iops,
+            max_cached_bandwidth,
+            cache_size_bytes,
         };
 
         Ok(sku)
     }
 }
 
+/// Disk holds the sku limits for a disk. When stored in Limits, it also
+/// carries the attachment details reported by IMDS for that device.
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Disk {
     pub location: String,
+    #[serde(default)]
+    pub kind: Kind,
     pub storage_account_type: String,
+    #[serde(default)]
+    pub tier: String,
     pub min_size_gb: u64,
     pub max_size_gb: u64,
-    pub max_iops: u64,
-    pub max_bandwidth: u64,
+    pub max_iops: Iops,
+    pub max_bandwidth: BytesPerSecond,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lun: Option<String>,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
+    #[serde(default)]
+    pub caching: String,
+    #[serde(default)]
+    pub size_gb: u64,
+    #[serde(default)]
+    pub bursting_enabled: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub burst: Option<Burst>,
+}
+
+/// Burst holds the credit based burst limits of a disk, and how long a
+/// full credit bucket sustains them.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Burst {
+    pub max_iops: Iops,
+    pub max_bandwidth: BytesPerSecond,
+    pub duration_secs: u64,
+}
+
+/// Kind distinguishes managed disks, which are metered against their
+/// own sku and the vm's uncached limits, from disks on host local
+/// storage. The temp disk and ephemeral os disks are only metered
+/// against the vm's combined temp and cached limits, while local nvme
+/// disks are not metered at all. Virtual disks are md or device-mapper
+/// devices, limited by their members, whose traffic is already metered
+/// on those members.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    #[default]
+    Managed,
+    Temporary,
+    Ephemeral,
+    Local,
+    Virtual,
+}
+
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Managed => "managed",
+            Kind::Temporary => "temporary",
+            Kind::Ephemeral => "ephemeral",
+            Kind::Local => "local",
+            Kind::Virtual => "virtual",
+        }
+    }
+
+    /// Returns whether the disk counts against the vm's combined temp
+    /// and cached limits only.
+    pub fn is_cached(&self) -> bool {
+        matches!(self, Kind::Temporary | Kind::Ephemeral)
+    }
+
+    /// Returns whether the disk has iops and bandwidth limits.
+    pub fn has_limits(&self) -> bool {
+        !matches!(self, Kind::Local)
+    }
 }
 
 impl TryFrom<Resource> for Disk {
     type Error = Error;
 
     fn try_from(value: Resource) -> Result<Self, Self::Error> {
-        if value.locations.len() < 1 {
+        if value.locations.is_empty() {
             return Err(eyre!("no locations available for provided sku"));
         }
 
         let location = value.locations[0].clone();
         let storage_account_type = value.name.clone();
+        let tier = value.size.clone().unwrap_or_default();
         let mut capabilities: HashMap<String, String> = HashMap::new();
         for cap in value.capabilities.clone() {
             capabilities.entry(cap.name).or_insert(cap.value);
         }
 
-        // Azure provides VM bandwith limits in B/s but disk limits in MB/s
-        let base: u64 = 2;
-        let exp = |n| -> u64 { n * base.pow(20) };
-
         let min_size_gb = capabilities
             .get("MinSizeGiB")
             .ok_or(eyre!("failed to find minimum disk sku size"))?
@@ -282,21 +579,27 @@ impl TryFrom<Resource> for Disk {
         let max_iops = capabilities
             .get("MaxIOps")
             .ok_or(eyre!("failed to find maximum disk sku iops"))?
-            .parse::<u64>()?;
+            .parse::<f64>()
+            .map(Iops)?;
 
+        // Azure provides VM bandwith limits in B/s but disk limits in MB/s
         let max_bandwidth = capabilities
             .get("MaxBandwidthMBps")
             .ok_or(eyre!("failed to find maximum disk sku bandwidth"))?
-            .parse::<u64>()
-            .map(exp)?;
+            .parse::<f64>()
+            .map(BytesPerSecond::from_megabytes)?;
 
+        let burst = burst::disk_burst(&tier);
         let sku = Disk {
             location,
             storage_account_type,
+            burst,
+            tier,
             min_size_gb,
             max_size_gb,
             max_iops,
             max_bandwidth,
+            ..Default::default()
         };
 
         Ok(sku)
@@ -0,0 +1,366 @@
+use eyre::Result;
+use std::collections::HashMap;
+use std::ops::AddAssign;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::burst::Credits;
+use super::config::{format_duration, Config, DeviceFilter, IoUnits};
+use super::diskstats::{self, Snapshot};
+use super::iostat;
+use super::metrics;
+use super::mountinfo;
+use super::resource::{Disk, Kind, Limits, VirtualMachine};
+use super::source::Source;
+use super::topology::{Class, Classifier};
+use super::units::{BytesPerSecond, Iops};
+use super::window::Series;
+
+/// Sampler periodically collects device statistics and records them in
+/// the metrics tracker, independently of Prometheus scrapes.
+pub struct Sampler {
+    metrics: metrics::Tracker,
+    source: Source,
+    vm_limit: VirtualMachine,
+    disk_limits: HashMap<String, Disk>,
+    interval: Duration,
+    windows: Vec<Duration>,
+    io_units: IoUnits,
+    iops_ratios: HashMap<String, Series>,
+    throughput_ratios: HashMap<String, Series>,
+    counters: HashMap<String, Snapshot>,
+    credits: HashMap<String, Credits>,
+    sampled_at: Instant,
+    classifier: Classifier,
+    device_filter: DeviceFilter,
+    mountinfo_path: String,
+}
+
+impl Sampler {
+    pub fn new(metrics: metrics::Tracker, source: Source, limits: Limits, config: &Config) -> Self {
+        let mut credits: HashMap<String, Credits> = limits
+            .disks
+            .iter()
+            .filter_map(|(device, disk)| {
+                let burst = disk.burst.as_ref()?;
+                let credits = Credits::new(disk.max_iops, disk.max_bandwidth, burst);
+                Some((device.clone(), credits))
+            })
+            .collect();
+
+        let vm = &limits.virtual_machine;
+        if let Some(burst) = &vm.burst {
+            let vm_credits = Credits::new(vm.max_iops, vm.max_bandwidth, burst);
+            credits.insert("TOTAL".to_string(), vm_credits);
+        }
+
+        Sampler {
+            metrics,
+            source,
+            vm_limit: limits.virtual_machine,
+            disk_limits: limits.disks,
+            interval: config.sample_interval,
+            windows: config.windows.clone(),
+            io_units: config.io_units.clone(),
+            iops_ratios: HashMap::new(),
+            throughput_ratios: HashMap::new(),
+            counters: HashMap::new(),
+            credits,
+            sampled_at: Instant::now(),
+            classifier: Classifier::default(),
+            device_filter: config.device_filter.clone(),
+            mountinfo_path: config.mountinfo_path.clone(),
+        }
+    }
+
+    /// Runs the sampling loop on a dedicated thread. Collection may block
+    /// (iostat samples for a full second), so it stays off the executor.
+    pub fn spawn(self) -> thread::JoinHandle<()> {
+        thread::spawn(move || self.run())
+    }
+
+    fn run(mut self) {
+        // wait a full interval before the first sample so that delta
+        // based sources average over a meaningful period.
+        let mut next = Instant::now() + self.interval;
+        loop {
+            thread::sleep(next.saturating_duration_since(Instant::now()));
+            // skip missed ticks rather than sampling back to back when
+            // collection takes longer than the interval.
+            next = (next + self.interval).max(Instant::now());
+
+            match self.collect() {
+                Ok(()) => self.metrics.mark_sampled(),
+                Err(e) => println!("failed to collect metrics: {}", e),
+            }
+        }
+    }
+
+    fn collect(&mut self) -> Result<()> {
+        let samples = self.source.sample()?;
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.sampled_at);
+        self.sampled_at = now;
+
+        self.metrics.set_cpu(&samples.cpu);
+
+        let mut total = Usage::default();
+        let mut cached = Usage::default();
+        let mut uncached = Usage::default();
+        for (disk, stats) in samples.disks.iter() {
+            let device = self.classifier.classify(disk).clone();
+            let disk_limit = self.disk_limits.get(disk);
+
+            let storage_account_type = disk_limit
+                .map(|limit| limit.storage_account_type.as_str())
+                .unwrap_or_default();
+            let io_unit_kib = self.io_units.size_kib(storage_account_type);
+
+            let usage = Usage::from(stats, io_unit_kib);
+
+            // reads served through the host cache count against the
+            // cached limit, everything else against the uncached one.
+            // The temp disk and ephemeral os disks are local to the host
+            // and only ever count against the cached limit, and local
+            // nvme disks against nothing at all. Virtual disk traffic is
+            // already counted on its members. Anything else, such as
+            // partitions and loop devices, isn't an Azure disk.
+            match disk_limit {
+                Some(disk_limit) if disk_limit.kind == Kind::Virtual => (),
+                Some(disk_limit) if disk_limit.kind.is_cached() => cached += usage,
+                Some(disk_limit) if !disk_limit.kind.has_limits() => (),
+                Some(disk_limit) => {
+                    total += usage;
+                    match disk_limit.caching.as_str() {
+                        "ReadWrite" => cached += usage,
+                        "ReadOnly" => {
+                            cached += usage.reads();
+                            uncached += usage.writes();
+                        }
+                        _ => uncached += usage,
+                    }
+                }
+                None => (),
+            }
+
+            // the filter only trims per-device series, filtered devices
+            // still count toward the vm totals above.
+            if !self.device_filter.allows(disk, device.class) {
+                continue;
+            }
+            self.metrics.set_device_info(disk, &device);
+
+            if disk_limit.is_none() && device.class == Class::Disk {
+                println!("no sku information stored for disk: {}", disk);
+            }
+
+            self.metrics.set_extended(disk, stats);
+
+            let limit = disk_limit
+                .filter(|limit| limit.kind.has_limits())
+                .map(|limit| (limit.max_iops, limit.max_bandwidth));
+
+            self.record(now, disk, &usage, limit);
+            self.record_burst(disk, &usage, elapsed);
+        }
+
+        let vm_limit = (self.vm_limit.max_iops, self.vm_limit.max_bandwidth);
+        self.record(now, "TOTAL", &total, Some(vm_limit));
+        self.record_burst("TOTAL", &total, elapsed);
+        self.record(now, "UNCACHED", &uncached, Some(vm_limit));
+
+        let cached_limit = self
+            .vm_limit
+            .max_cached_iops
+            .zip(self.vm_limit.max_cached_bandwidth);
+        self.record(now, "CACHED", &cached, cached_limit);
+
+        self.record_windows(now);
+        self.record_counters()?;
+
+        // mounts only label other metrics, so don't fail the sample.
+        if let Err(e) = self.record_mounts() {
+            println!("failed to read mounts: {}", e);
+        }
+
+        Ok(())
+    }
+
+    /// Records usage for a device or host, and its ratios against limit
+    /// (max iops, max bandwidth) when known.
+    fn record(
+        &mut self,
+        now: Instant,
+        label: &str,
+        usage: &Usage,
+        limit: Option<(Iops, BytesPerSecond)>,
+    ) {
+        let metrics = &self.metrics;
+
+        metrics.set_iops(label, usage.iops());
+        metrics.set_effective_iops(label, usage.effective_iops());
+        metrics.set_throughput(label, usage.throughput());
+        metrics.set_iops_by_direction(label, usage.read_iops, usage.write_iops);
+        metrics.set_throughput_by_direction(label, usage.read_throughput, usage.write_throughput);
+
+        let (max_iops, max_bandwidth) = match limit {
+            Some(limit) => limit,
+            None => return,
+        };
+
+        let iops_ratio = normalize(usage.iops() / max_iops, 6);
+        let throughput_ratio = normalize(usage.throughput() / max_bandwidth, 6);
+
+        metrics.set_iops_ratio(label, iops_ratio);
+        metrics.set_effective_iops_ratio(label, normalize(usage.effective_iops() / max_iops, 6));
+        metrics.set_throughput_ratio(label, throughput_ratio);
+        metrics.set_iops_ratio_by_direction(
+            label,
+            normalize(usage.read_iops / max_iops, 6),
+            normalize(usage.write_iops / max_iops, 6),
+        );
+        metrics.set_throughput_ratio_by_direction(
+            label,
+            normalize(usage.read_throughput / max_bandwidth, 6),
+            normalize(usage.write_throughput / max_bandwidth, 6),
+        );
+
+        let retention = self.windows.iter().max().cloned().unwrap_or_default();
+        self.iops_ratios
+            .entry(label.to_string())
+            .or_insert_with(|| Series::new(retention))
+            .push(now, iops_ratio);
+        self.throughput_ratios
+            .entry(label.to_string())
+            .or_insert_with(|| Series::new(retention))
+            .push(now, throughput_ratio);
+    }
+
+    /// Advances the burst credit estimate of a device or host which
+    /// supports credit based bursting.
+    fn record_burst(&mut self, label: &str, usage: &Usage, elapsed: Duration) {
+        if let Some(credits) = self.credits.get_mut(label) {
+            credits.update(usage.iops(), usage.throughput(), elapsed);
+            self.metrics
+                .set_burst(label, credits, usage.iops(), usage.throughput());
+        }
+    }
+
+    /// Advances the monotonic counters from /proc/diskstats regardless
+    /// of the configured source. Devices seen for the first time start
+    /// from their absolute kernel counters.
+    fn record_counters(&mut self) -> Result<()> {
+        let mut current = diskstats::read()?;
+        let classifier = &mut self.classifier;
+        let device_filter = &self.device_filter;
+        current.retain(|device, _| device_filter.allows(device, classifier.classify(device).class));
+
+        for (device, snapshot) in current.iter() {
+            let previous = self.counters.get(device).cloned().unwrap_or_default();
+            self.metrics
+                .inc_counters(device, &snapshot.delta(&previous));
+        }
+        self.counters = current;
+        Ok(())
+    }
+
+    fn record_mounts(&mut self) -> Result<()> {
+        let mut mounts = mountinfo::read(&self.mountinfo_path)?;
+        let classifier = &mut self.classifier;
+        let device_filter = &self.device_filter;
+        mounts.retain(|mount| {
+            device_filter.allows(&mount.device, classifier.classify(&mount.device).class)
+        });
+        self.metrics.set_mounts(&mounts);
+        Ok(())
+    }
+
+    fn record_windows(&self, now: Instant) {
+        for window in self.windows.iter() {
+            let label = format_duration(window);
+            for (device, series) in self.iops_ratios.iter() {
+                if let Some(aggregate) = series.aggregate(now, *window) {
+                    self.metrics
+                        .set_iops_ratio_window(device, &label, &aggregate);
+                }
+            }
+            for (device, series) in self.throughput_ratios.iter() {
+                if let Some(aggregate) = series.aggregate(now, *window) {
+                    self.metrics
+                        .set_throughput_ratio_window(device, &label, &aggregate);
+                }
+            }
+        }
+    }
+}
+
+/// Usage holds point-in-time IOPS and throughput split by direction,
+/// and IOPS as Azure meters them.
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+struct Usage {
+    read_iops: Iops,
+    write_iops: Iops,
+    read_effective_iops: Iops,
+    write_effective_iops: Iops,
+    read_throughput: BytesPerSecond,
+    write_throughput: BytesPerSecond,
+}
+
+impl Usage {
+    fn from(stats: &iostat::Disk, io_unit_kib: u64) -> Self {
+        Usage {
+            read_iops: stats.read_iops(),
+            write_iops: stats.write_iops(),
+            read_effective_iops: stats.read_effective_iops(io_unit_kib),
+            write_effective_iops: stats.write_effective_iops(io_unit_kib),
+            read_throughput: stats.read_throughput(),
+            write_throughput: stats.write_throughput(),
+        }
+    }
+
+    fn iops(&self) -> Iops {
+        self.read_iops + self.write_iops
+    }
+
+    fn effective_iops(&self) -> Iops {
+        self.read_effective_iops + self.write_effective_iops
+    }
+
+    fn reads(&self) -> Usage {
+        Usage {
+            read_iops: self.read_iops,
+            read_effective_iops: self.read_effective_iops,
+            read_throughput: self.read_throughput,
+            ..Default::default()
+        }
+    }
+
+    fn writes(&self) -> Usage {
+        Usage {
+            write_iops: self.write_iops,
+            write_effective_iops: self.write_effective_iops,
+            write_throughput: self.write_throughput,
+            ..Default::default()
+        }
+    }
+
+    fn throughput(&self) -> BytesPerSecond {
+        self.read_throughput + self.write_throughput
+    }
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, other: Usage) {
+        self.read_iops += other.read_iops;
+        self.write_iops += other.write_iops;
+        self.read_effective_iops += other.read_effective_iops;
+        self.write_effective_iops += other.write_effective_iops;
+        self.read_throughput += other.read_throughput;
+        self.write_throughput += other.write_throughput;
+    }
+}
+
+fn normalize(ratio: f64, accuracy: i32) -> f64 {
+    let coefficient = 10_f64.powi(accuracy);
+    (coefficient * ratio).round() / coefficient
+}
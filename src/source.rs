@@ -0,0 +1,32 @@
+use eyre::Result;
+
+use super::config::Collector;
+use super::diskstats;
+use super::iostat;
+use super::procstat;
+
+pub fn new(collector: Collector) -> Result<Source> {
+    match collector {
+        Collector::Iostat => Ok(Source::Iostat),
+        Collector::Diskstats => Ok(Source::Diskstats(diskstats::new()?, procstat::new()?)),
+    }
+}
+
+/// Source produces cpu and per-device statistics using the configured
+/// collector.
+pub enum Source {
+    Iostat,
+    Diskstats(diskstats::Collector, procstat::Collector),
+}
+
+impl Source {
+    pub fn sample(&mut self) -> Result<iostat::Sample> {
+        match self {
+            Source::Iostat => iostat::new(),
+            Source::Diskstats(disks, cpu) => Ok(iostat::Sample {
+                cpu: cpu.sample()?,
+                disks: disks.sample()?,
+            }),
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use eyre::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use super::discovery::list_dir;
+use super::resource::{Disk, Kind};
+use super::units::{BytesPerSecond, Iops};
+
+const SYS_BLOCK_DIR: &str = "/sys/block";
+const SYS_CLASS_BLOCK_DIR: &str = "/sys/class/block";
+
+// role markers in the names of LVM image sub-volumes.
+const MIRROR_IMAGE: &str = "_mimage_";
+const RAID_IMAGE: &str = "_rimage_";
+
+/// Class is the kind of block device, as far as metering goes. Only
+/// whole disks are backed by Azure disks; everything else is either a
+/// part of one or stacked on top of them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Class {
+    Disk,
+    Partition,
+    Loop,
+    Ram,
+    Dm,
+    Md,
+}
+
+impl Class {
+    pub const ALL: [Class; 6] = [
+        Class::Disk,
+        Class::Partition,
+        Class::Loop,
+        Class::Ram,
+        Class::Dm,
+        Class::Md,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Class::Disk => "disk",
+            Class::Partition => "partition",
+            Class::Loop => "loop",
+            Class::Ram => "ram",
+            Class::Dm => "dm",
+            Class::Md => "md",
+        }
+    }
+}
+
+/// Device holds the class of a block device, and the whole disk it
+/// belongs to when it is a partition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Device {
+    pub class: Class,
+    pub parent: Option<String>,
+}
+
+/// Classifier caches device classes by label, e.g. /dev/sda1, since a
+/// device's class doesn't change while it exists.
+#[derive(Default, Debug, Clone)]
+pub struct Classifier {
+    devices: HashMap<String, Device>,
+}
+
+impl Classifier {
+    pub fn classify(&mut self, label: &str) -> &Device {
+        self.devices.entry(label.to_string()).or_insert_with(|| {
+            classify(label.trim_start_matches("/dev/")).unwrap_or_else(|e| {
+                println!("failed to classify device {}: {}", label, e);
+                Device {
+                    class: Class::Disk,
+                    parent: None,
+                }
+            })
+        })
+    }
+}
+
+/// Classifies a block device by name, e.g. sda1, from sysfs.
+pub fn classify(name: &str) -> Result<Device> {
+    if Path::new(&format!("{}/{}/partition", SYS_CLASS_BLOCK_DIR, name)).exists() {
+        return Ok(Device {
+            class: Class::Partition,
+            parent: Some(format!("/dev/{}", whole_disk(name)?)),
+        });
+    }
+
+    let class = if name.starts_with("loop") {
+        Class::Loop
+    } else if name.starts_with("ram") || name.starts_with("zram") {
+        Class::Ram
+    } else if name.starts_with("dm-") {
+        Class::Dm
+    } else if name.starts_with("md") {
+        Class::Md
+    } else {
+        Class::Disk
+    };
+
+    Ok(Device {
+        class,
+        parent: None,
+    })
+}
+
+/// Synthesizes limits for device-mapper and md devices stacked on top of
+/// disks with known limits, by following /sys/block/*/slaves down to the
+/// member disks. md raid1 and LVM mirrors are limited by their slowest
+/// image, md linear and raid0 and other device-mapper targets (striping,
+/// linear concatenation, encryption) by the sum of their members. Other
+/// md levels and LVM raid volumes, whose level sysfs doesn't expose, are
+/// skipped, as are devices with any member of unknown limits.
+pub fn virtual_disks(disks: &HashMap<String, Disk>) -> Result<HashMap<String, Disk>> {
+    let mut virtual_disks: HashMap<String, Disk> = HashMap::new();
+    for name in list_dir(SYS_BLOCK_DIR)? {
+        resolve(&name, disks, &mut virtual_disks)?;
+    }
+
+    Ok(virtual_disks)
+}
+
+fn resolve(
+    name: &str,
+    disks: &HashMap<String, Disk>,
+    virtual_disks: &mut HashMap<String, Disk>,
+) -> Result<Option<(Iops, BytesPerSecond)>> {
+    let device = format!("/dev/{}", name);
+    if let Some(disk) = disks.get(&device).or_else(|| virtual_disks.get(&device)) {
+        if !disk.kind.has_limits() {
+            return Ok(None);
+        }
+        return Ok(Some((disk.max_iops, disk.max_bandwidth)));
+    }
+
+    let mut members = list_dir(&format!("{}/{}/slaves", SYS_BLOCK_DIR, name))?
+        .iter()
+        .map(|member| whole_disk(member))
+        .collect::<Result<Vec<String>>>()?;
+    members.sort();
+    members.dedup();
+
+    let mirror = match layout(name, &members) {
+        Layout::Concat => false,
+        Layout::Mirror => true,
+        Layout::Unknown => return Ok(None),
+    };
+
+    // only the images of an LVM mirror carry data, not its log.
+    if members
+        .iter()
+        .any(|member| is_lvm_image(member, MIRROR_IMAGE))
+    {
+        members.retain(|member| is_lvm_image(member, MIRROR_IMAGE));
+    }
+
+    if members.is_empty() {
+        return Ok(None);
+    }
+
+    let mut limits = Vec::new();
+    for member in members.iter() {
+        match resolve(member, disks, virtual_disks)? {
+            Some(limit) => limits.push(limit),
+            None => return Ok(None),
+        }
+    }
+
+    let combine = |values: Vec<f64>| -> f64 {
+        if mirror {
+            values.into_iter().fold(f64::INFINITY, f64::min)
+        } else {
+            values.into_iter().sum()
+        }
+    };
+    let max_iops = Iops(combine(limits.iter().map(|(iops, _)| iops.0).collect()));
+    let max_bandwidth = BytesPerSecond(combine(
+        limits.iter().map(|(_, bandwidth)| bandwidth.0).collect(),
+    ));
+
+    let disk = Disk {
+        kind: Kind::Virtual,
+        name: members
+            .iter()
+            .map(|member| format!("/dev/{}", member))
+            .collect::<Vec<String>>()
+            .join(","),
+        max_iops,
+        max_bandwidth,
+        ..Default::default()
+    };
+    virtual_disks.insert(device, disk);
+
+    Ok(Some((max_iops, max_bandwidth)))
+}
+
+/// Returns the whole disk a partition belongs to, or the device itself
+/// when it isn't a partition.
+fn whole_disk(name: &str) -> Result<String> {
+    let dir = format!("{}/{}", SYS_CLASS_BLOCK_DIR, name);
+    if !Path::new(&dir).join("partition").exists() {
+        return Ok(name.to_string());
+    }
+
+    // partitions live in the sysfs directory of their parent disk.
+    let path = fs::canonicalize(&dir).wrap_err_with(|| format!("failed to resolve {}", dir))?;
+    Ok(path
+        .parent()
+        .and_then(|parent| parent.file_name())
+        .and_then(|parent| parent.to_str())
+        .unwrap_or(name)
+        .to_string())
+}
+
+/// Layout is how a stacked device spreads IO across its members.
+enum Layout {
+    Concat,
+    Mirror,
+    Unknown,
+}
+
+fn layout(name: &str, members: &[String]) -> Layout {
+    if let Ok(level) = fs::read_to_string(format!("{}/{}/md/level", SYS_BLOCK_DIR, name)) {
+        return match level.trim() {
+            "raid1" => Layout::Mirror,
+            "linear" | "raid0" => Layout::Concat,
+            _ => Layout::Unknown,
+        };
+    }
+
+    if members
+        .iter()
+        .any(|member| is_lvm_image(member, MIRROR_IMAGE))
+    {
+        Layout::Mirror
+    } else if members
+        .iter()
+        .any(|member| is_lvm_image(member, RAID_IMAGE))
+    {
+        Layout::Unknown
+    } else {
+        Layout::Concat
+    }
+}
+
+/// Returns whether a device is an image sub-volume of an LVM logical
+/// volume, which LVM names after the volume and its role, e.g.
+/// vg-lv_mimage_0 for a mirror or vg-lv_rimage_0 for raid.
+fn is_lvm_image(name: &str, role: &str) -> bool {
+    let read = |file: &str| fs::read_to_string(format!("{}/{}/dm/{}", SYS_BLOCK_DIR, name, file));
+    match (read("uuid"), read("name")) {
+        (Ok(uuid), Ok(dm_name)) => uuid.starts_with("LVM-") && dm_name.contains(role),
+        _ => false,
+    }
+}
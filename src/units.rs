@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+use std::ops::{Add, AddAssign, Div, Mul};
+
+/// Iops is a rate of I/O operations per second.
+#[derive(Default, Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Iops(pub f64);
+
+/// BytesPerSecond is a bandwidth in bytes per second.
+#[derive(Default, Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct BytesPerSecond(pub f64);
+
+const KIB: f64 = 1024.0;
+const MIB: f64 = 1024.0 * 1024.0;
+
+impl BytesPerSecond {
+    /// Converts kB/s as reported by iostat, where a kB is 1024 bytes.
+    pub fn from_kilobytes(value: f64) -> Self {
+        BytesPerSecond(value * KIB)
+    }
+
+    /// Converts MB/s as reported by the disk SKU API, where a MB is
+    /// 1024 * 1024 bytes.
+    pub fn from_megabytes(value: f64) -> Self {
+        BytesPerSecond(value * MIB)
+    }
+}
+
+macro_rules! unit {
+    ($unit:ident) => {
+        impl Add for $unit {
+            type Output = $unit;
+
+            fn add(self, other: $unit) -> $unit {
+                $unit(self.0 + other.0)
+            }
+        }
+
+        impl AddAssign for $unit {
+            fn add_assign(&mut self, other: $unit) {
+                self.0 += other.0;
+            }
+        }
+
+        impl Mul<f64> for $unit {
+            type Output = $unit;
+
+            fn mul(self, scale: f64) -> $unit {
+                $unit(self.0 * scale)
+            }
+        }
+
+        /// Dividing two values of the same unit yields a dimensionless
+        /// ratio, so usage can only be compared against a matching limit.
+        impl Div for $unit {
+            type Output = f64;
+
+            fn div(self, other: $unit) -> f64 {
+                self.0 / other.0
+            }
+        }
+    };
+}
+
+unit!(Iops);
+unit!(BytesPerSecond);
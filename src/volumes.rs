@@ -0,0 +1,266 @@
+use eyre::Result;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+
+use super::kube::{self, Client};
+use super::metrics;
+use super::mountinfo;
+use super::resource::{Disk, Kind};
+
+const AZURE_DISK_CSI_DRIVER: &str = "disk.csi.azure.com";
+
+/// Volume holds the Kubernetes PersistentVolume backed by a device, its
+/// claim, and the pods on this node using it.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Volume {
+    pub persistent_volume: String,
+    pub namespace: String,
+    pub claim: String,
+    pub pods: Vec<String>,
+}
+
+/// Refresher periodically maps devices to the Azure Disk CSI volumes
+/// attached to this node and records them in the metrics tracker.
+pub struct Refresher {
+    metrics: metrics::Tracker,
+    client: Client,
+    node_name: String,
+    disk_limits: HashMap<String, Disk>,
+    mountinfo_path: String,
+    interval: Duration,
+}
+
+impl Refresher {
+    pub fn new(
+        metrics: metrics::Tracker,
+        node_name: String,
+        disk_limits: HashMap<String, Disk>,
+        mountinfo_path: String,
+        interval: Duration,
+    ) -> Result<Self> {
+        Ok(Refresher {
+            metrics,
+            client: kube::in_cluster()?,
+            node_name,
+            disk_limits,
+            mountinfo_path,
+            interval,
+        })
+    }
+
+    /// Refreshes volumes every interval. Pods and volumes come and go,
+    /// so failures are logged and retried on the next interval.
+    pub async fn run(self) {
+        loop {
+            match self.refresh().await {
+                Ok(volumes) => self.metrics.set_volumes(volumes),
+                Err(e) => println!("failed to refresh volumes: {}", e),
+            }
+            smol::Timer::after(self.interval).await;
+        }
+    }
+
+    async fn refresh(&self) -> Result<HashMap<String, Volume>> {
+        let mounts = mountinfo::read(&self.mountinfo_path)?;
+        let persistent_volumes = self.client.list_persistent_volumes().await?.items;
+        let pods = self.client.list_pods(&self.node_name).await?.items;
+
+        // kubelet mounts each csi volume for pods under a path naming the
+        // persistent volume and the pod uid, and globally under a path
+        // naming the persistent volume or a hash of its volume handle.
+        let mut devices_by_volume: HashMap<&str, &str> = HashMap::new();
+        let mut devices_by_handle_hash: HashMap<&str, &str> = HashMap::new();
+        let mut pod_uids_by_volume: HashMap<&str, Vec<&str>> = HashMap::new();
+        for mount in mounts.iter() {
+            match parse_kubelet_path(&mount.mountpoint) {
+                Some(KubeletPath::Pod {
+                    persistent_volume,
+                    pod_uid,
+                }) => {
+                    devices_by_volume.insert(persistent_volume, &mount.device);
+                    if let Some(pod_uid) = pod_uid {
+                        pod_uids_by_volume
+                            .entry(persistent_volume)
+                            .or_default()
+                            .push(pod_uid);
+                    }
+                }
+                Some(KubeletPath::GlobalVolume(persistent_volume)) => {
+                    devices_by_volume.insert(persistent_volume, &mount.device);
+                }
+                Some(KubeletPath::GlobalHandle(handle_hash)) => {
+                    devices_by_handle_hash.insert(handle_hash, &mount.device);
+                }
+                None => (),
+            }
+        }
+
+        // the csi volume handle is the managed disk id, which IMDS
+        // reports for each attached disk. ARM ids are case insensitive.
+        let devices_by_disk_id: HashMap<String, &str> = self
+            .disk_limits
+            .iter()
+            .filter(|(_, disk)| disk.kind == Kind::Managed && !disk.id.is_empty())
+            .map(|(device, disk)| (disk.id.to_lowercase(), device.as_str()))
+            .collect();
+
+        let mut volumes: HashMap<String, Volume> = HashMap::new();
+        for persistent_volume in persistent_volumes.iter() {
+            let csi = match &persistent_volume.spec.csi {
+                Some(csi) if csi.driver == AZURE_DISK_CSI_DRIVER => csi,
+                _ => continue,
+            };
+
+            let name = persistent_volume.metadata.name.as_str();
+            let handle_hash = handle_hash(&csi.volume_handle);
+            let device = match devices_by_volume
+                .get(name)
+                .or_else(|| devices_by_handle_hash.get(handle_hash.as_str()))
+                .copied()
+                .or_else(|| {
+                    devices_by_disk_id
+                        .get(&csi.volume_handle.to_lowercase())
+                        .copied()
+                }) {
+                Some(device) => device,
+                None => continue,
+            };
+
+            let claim_ref = persistent_volume.spec.claim_ref.clone().unwrap_or_default();
+            let pod_uids = pod_uids_by_volume.get(name).cloned().unwrap_or_default();
+            let pods = pods
+                .iter()
+                .filter(|pod| {
+                    pod_uids.contains(&pod.metadata.uid.as_str())
+                        || (pod.metadata.namespace == claim_ref.namespace
+                            && pod.spec.volumes.iter().any(|volume| {
+                                matches!(&volume.persistent_volume_claim,
+                                    Some(source) if source.claim_name == claim_ref.name)
+                            }))
+                })
+                .map(|pod| pod.metadata.name.clone())
+                .collect::<BTreeSet<String>>();
+
+            volumes.insert(
+                device.to_string(),
+                Volume {
+                    persistent_volume: name.to_string(),
+                    namespace: claim_ref.namespace,
+                    claim: claim_ref.name,
+                    pods: pods.into_iter().collect(),
+                },
+            );
+        }
+
+        Ok(volumes)
+    }
+}
+
+/// Returns the hex sha256 of a csi volume handle, which current kubelets
+/// name the global mount directory of the volume after.
+fn handle_hash(volume_handle: &str) -> String {
+    format!("{:x}", Sha256::digest(volume_handle.as_bytes()))
+}
+
+/// KubeletPath is what a kubelet csi mount path names.
+#[derive(Debug, PartialEq)]
+enum KubeletPath<'a> {
+    /// A pod's mount of a persistent volume, e.g.
+    /// /var/lib/kubelet/pods/<uid>/volumes/kubernetes.io~csi/<pv>/mount.
+    Pod {
+        persistent_volume: &'a str,
+        pod_uid: Option<&'a str>,
+    },
+    /// The global mount of a persistent volume by older kubelets, e.g.
+    /// /var/lib/kubelet/plugins/kubernetes.io/csi/pv/<pv>/globalmount.
+    GlobalVolume(&'a str),
+    /// The global mount by current kubelets, named by the hex sha256 of
+    /// the volume handle, e.g.
+    /// /var/lib/kubelet/plugins/kubernetes.io/csi/<driver>/<sha256>/globalmount.
+    GlobalHandle(&'a str),
+}
+
+fn parse_kubelet_path(mountpoint: &str) -> Option<KubeletPath<'_>> {
+    let segments: Vec<&str> = mountpoint.split('/').collect();
+
+    if let Some(i) = segments.iter().position(|s| *s == "kubernetes.io~csi") {
+        let persistent_volume = segments.get(i + 1)?;
+        let pod_uid = segments
+            .iter()
+            .position(|s| *s == "pods")
+            .and_then(|j| segments.get(j + 1))
+            .copied();
+        return Some(KubeletPath::Pod {
+            persistent_volume,
+            pod_uid,
+        });
+    }
+
+    segments
+        .windows(4)
+        .find(|w| w[0] == "csi" && w[3] == "globalmount")
+        .map(|w| match w[1] {
+            "pv" => KubeletPath::GlobalVolume(w[2]),
+            _ => KubeletPath::GlobalHandle(w[2]),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VOLUME_HANDLE: &str = "/subscriptions/00000000-0000-0000-0000-000000000000/resourceGroups/mc_rg_aks_eastus/providers/Microsoft.Compute/disks/pvc-0a1b2c3d";
+    const HANDLE_HASH: &str = "b5d5f059a1aaeeb53e3f4aa97151009b51fa4b3f250f2ccb5f8998573871a5c3";
+
+    #[test]
+    fn parse_pod_mount() {
+        assert_eq!(
+            parse_kubelet_path(
+                "/var/lib/kubelet/pods/6f1c2a4e-8b0d-4c1e-9f3a-2d7b5e0c9a11/volumes/kubernetes.io~csi/pvc-0a1b2c3d/mount"
+            ),
+            Some(KubeletPath::Pod {
+                persistent_volume: "pvc-0a1b2c3d",
+                pod_uid: Some("6f1c2a4e-8b0d-4c1e-9f3a-2d7b5e0c9a11"),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_legacy_global_mount() {
+        assert_eq!(
+            parse_kubelet_path(
+                "/var/lib/kubelet/plugins/kubernetes.io/csi/pv/pvc-0a1b2c3d/globalmount"
+            ),
+            Some(KubeletPath::GlobalVolume("pvc-0a1b2c3d"))
+        );
+    }
+
+    #[test]
+    fn parse_global_mount_by_handle_hash() {
+        let mountpoint = format!(
+            "/var/lib/kubelet/plugins/kubernetes.io/csi/disk.csi.azure.com/{}/globalmount",
+            HANDLE_HASH
+        );
+        assert_eq!(
+            parse_kubelet_path(&mountpoint),
+            Some(KubeletPath::GlobalHandle(HANDLE_HASH))
+        );
+    }
+
+    #[test]
+    fn parse_non_csi_mount() {
+        assert_eq!(parse_kubelet_path("/var/lib/docker"), None);
+        assert_eq!(
+            parse_kubelet_path(
+                "/var/lib/kubelet/pods/6f1c2a4e-8b0d-4c1e-9f3a-2d7b5e0c9a11/volumes/kubernetes.io~empty-dir/cache"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn handle_hash_matches_kubelet() {
+        assert_eq!(handle_hash(VOLUME_HANDLE), HANDLE_HASH);
+    }
+}
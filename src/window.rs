@@ -0,0 +1,127 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Series is a ring buffer of timestamped samples covering at most the
+/// retention period, used to aggregate over rolling windows.
+#[derive(Debug, Clone)]
+pub struct Series {
+    samples: VecDeque<(Instant, f64)>,
+    retention: Duration,
+}
+
+/// Aggregate summarizes the samples within a single window.
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub struct Aggregate {
+    pub avg: f64,
+    pub p95: f64,
+    pub max: f64,
+}
+
+impl Series {
+    pub fn new(retention: Duration) -> Self {
+        Series {
+            samples: VecDeque::new(),
+            retention,
+        }
+    }
+
+    pub fn push(&mut self, at: Instant, value: f64) {
+        self.samples.push_back((at, value));
+        while let Some((oldest, _)) = self.samples.front() {
+            if at.duration_since(*oldest) <= self.retention {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+
+    /// Returns the aggregate of all samples taken within window of now,
+    /// or None if there are none.
+    pub fn aggregate(&self, now: Instant, window: Duration) -> Option<Aggregate> {
+        let mut values: Vec<f64> = self
+            .samples
+            .iter()
+            .filter(|(at, _)| now.duration_since(*at) <= window)
+            .map(|(_, value)| *value)
+            .collect();
+
+        if values.is_empty() {
+            return None;
+        }
+
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+        // nearest-rank percentile
+        let rank = (0.95 * values.len() as f64).ceil() as usize;
+        let p95 = values[rank.saturating_sub(1)];
+        let max = values[values.len() - 1];
+        let avg = values.iter().sum::<f64>() / values.len() as f64;
+
+        Some(Aggregate { avg, p95, max })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // samples every 5s from 0s to 195s with the value of their index,
+    // so 0.0 at 0s up to 39.0 at 195s.
+    fn series(start: Instant, retention: Duration) -> Series {
+        let mut series = Series::new(retention);
+        for i in 0..40 {
+            series.push(start + Duration::from_secs(i * 5), i as f64);
+        }
+        series
+    }
+
+    #[test]
+    fn aggregate_per_window() {
+        let start = Instant::now();
+        let series = series(start, Duration::from_secs(300));
+        let now = start + Duration::from_secs(195);
+
+        // 185s, 190s and 195s.
+        assert_eq!(
+            series.aggregate(now, Duration::from_secs(10)),
+            Some(Aggregate {
+                avg: 38.0,
+                p95: 39.0,
+                max: 39.0,
+            })
+        );
+
+        // 100s to 195s, 20 samples, so the p95 is the 19th.
+        assert_eq!(
+            series.aggregate(now, Duration::from_secs(95)),
+            Some(Aggregate {
+                avg: 29.5,
+                p95: 38.0,
+                max: 39.0,
+            })
+        );
+
+        assert_eq!(
+            series.aggregate(now + Duration::from_secs(60), Duration::from_secs(10)),
+            None
+        );
+    }
+
+    #[test]
+    fn push_drops_samples_older_than_retention() {
+        let start = Instant::now();
+        let series = series(start, Duration::from_secs(100));
+        let now = start + Duration::from_secs(195);
+
+        // only 95s to 195s are retained, however long the window.
+        assert_eq!(
+            series.aggregate(now, Duration::from_secs(1000)),
+            Some(Aggregate {
+                avg: 29.0,
+                p95: 38.0,
+                max: 39.0,
+            })
+        );
+        assert_eq!(series.samples.len(), 21);
+    }
+}